@@ -0,0 +1,470 @@
+use axum::extract::Request;
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::info;
+
+use crate::config::{check_rate_limits, CONFIG};
+use crate::errors::{handle_error, ErrorCode};
+use crate::metrics::API_KEY_REQUESTS;
+use crate::types::{KeyUsage, UsageResponse};
+
+// "name:key" pairs, comma separated, added to the keys from the keys file
+pub const API_KEYS_ENV: &str = "AI_API_KEYS";
+
+const RATE_LIMIT_LIMIT: &str = "ratelimit-limit";
+const RATE_LIMIT_REMAINING: &str = "ratelimit-remaining";
+const RATE_LIMIT_RESET: &str = "ratelimit-reset";
+
+const METRICS_PATH: &str = "/metrics";
+
+lazy_static! {
+    // None when no keys are configured, which leaves every route open
+    pub static ref API_KEYS: Option<KeyStore> = KeyStore::load().expect("Failed to load API keys");
+}
+
+#[derive(Deserialize)]
+struct KeysFile {
+    #[serde(default)]
+    keys: Vec<KeyConfig>,
+}
+
+// One [[keys]] entry; unset limits fall back to the [auth] section
+#[derive(Deserialize)]
+struct KeyConfig {
+    name: String, // Reported in metrics and usage, never the key itself
+    key: String,
+    requests_per_second: Option<f64>,
+    burst: Option<u32>,
+    max_concurrent: Option<usize>,
+    #[serde(default)]
+    admin: bool, // Required for /admin routes
+    #[serde(default)]
+    disabled: bool, // Known but refused with 403, e.g. for unpaid accounts
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct RateLimit {
+    allowed: bool,
+    remaining: u64,
+    reset_secs: u64,       // Until the bucket is full again
+    retry_after_secs: u64, // Until the next token, when refused
+}
+
+pub struct ApiKey {
+    name: String,
+    key: String,
+    admin: bool,
+    disabled: bool,
+    requests_per_second: f64,
+    burst: u32,
+    max_concurrent: usize,
+    bucket: Mutex<Bucket>,
+    in_flight: AtomicUsize,
+    requests: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+// Releases a concurrency slot when the request finishes, even if the handler panics
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ApiKey {
+    fn new(config: KeyConfig) -> Result<ApiKey, String> {
+        let auth = &CONFIG.auth;
+        let requests_per_second = config
+            .requests_per_second
+            .unwrap_or(auth.requests_per_second);
+        let burst = config.burst.unwrap_or(auth.burst);
+        let max_concurrent = config.max_concurrent.unwrap_or(auth.max_concurrent);
+        check_rate_limits(
+            &format!("API key {}", config.name),
+            requests_per_second,
+            burst,
+            max_concurrent,
+        )?;
+
+        Ok(ApiKey {
+            name: config.name,
+            key: config.key,
+            admin: config.admin,
+            disabled: config.disabled,
+            requests_per_second,
+            burst,
+            max_concurrent,
+            bucket: Mutex::new(Bucket {
+                tokens: burst as f64,
+                updated: Instant::now(),
+            }),
+            in_flight: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            succeeded: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+        })
+    }
+
+    fn try_acquire(&self) -> Option<InFlightGuard<'_>> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < self.max_concurrent).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| InFlightGuard(&self.in_flight))
+    }
+
+    // Refills the bucket for the time since the last request, then takes a token if one is
+    // left; with take false it only reports the bucket, for requests refused before it
+    fn take_token(&self, now: Instant, take: bool) -> RateLimit {
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        let burst = self.burst as f64;
+        bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(burst);
+        bucket.updated = bucket.updated.max(now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed && take {
+            bucket.tokens -= 1.0;
+        }
+        let seconds = |tokens: f64| (tokens / self.requests_per_second).ceil() as u64;
+        RateLimit {
+            allowed,
+            remaining: bucket.tokens.floor() as u64,
+            reset_secs: seconds(burst - bucket.tokens),
+            retry_after_secs: seconds(1.0 - bucket.tokens).max(1),
+        }
+    }
+
+    // Takes a concurrency slot, then a token; the guard holds the slot until the request ends,
+    // and a refusal is the 429 to send instead
+    fn admit(&self, now: Instant) -> (Result<InFlightGuard<'_>, Response>, RateLimit) {
+        let Some(guard) = self.try_acquire() else {
+            let mut response = handle_error(
+                ErrorCode::ConcurrencyLimited,
+                format!("{} requests already in flight", self.max_concurrent),
+            )
+            .into_response();
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(CONFIG.retry_after_secs));
+            return (Err(response), self.take_token(now, false));
+        };
+        let limit = self.take_token(now, true);
+        if limit.allowed {
+            return (Ok(guard), limit);
+        }
+        let mut response = handle_error(
+            ErrorCode::RateLimited,
+            format!("{} requests per second", self.requests_per_second),
+        )
+        .into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(limit.retry_after_secs));
+        (Err(response), limit)
+    }
+
+    fn add_rate_limit_headers(&self, response: &mut Response, limit: &RateLimit) {
+        let headers = response.headers_mut();
+        headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(self.burst));
+        headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(limit.remaining));
+        headers.insert(RATE_LIMIT_RESET, HeaderValue::from(limit.reset_secs));
+    }
+
+    fn record(&self, status: StatusCode) {
+        let counter = if status.is_success() {
+            &self.succeeded
+        } else if status == StatusCode::TOO_MANY_REQUESTS {
+            &self.rate_limited
+        } else {
+            &self.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        API_KEY_REQUESTS
+            .with_label_values(&[self.name.as_str(), status.as_str()])
+            .inc();
+    }
+
+    fn usage(&self) -> KeyUsage {
+        KeyUsage {
+            name: self.name.clone(),
+            requests: self.requests.load(Ordering::Relaxed),
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct KeyStore {
+    keys: Vec<ApiKey>,
+}
+
+impl KeyStore {
+    fn load() -> Result<Option<KeyStore>, String> {
+        let mut configs = Vec::new();
+        if let Some(path) = &CONFIG.auth.keys_path {
+            let content = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+            let file: KeysFile =
+                toml::from_str(&content).map_err(|err| format!("{}: {}", path, err))?;
+            configs.extend(file.keys);
+        }
+        if let Ok(pairs) = env::var(API_KEYS_ENV) {
+            for pair in pairs
+                .split(',')
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty())
+            {
+                let (name, key) = pair
+                    .split_once(':')
+                    .ok_or_else(|| format!("{} entries must be name:key", API_KEYS_ENV))?;
+                configs.push(KeyConfig {
+                    name: name.trim().to_string(),
+                    key: key.trim().to_string(),
+                    requests_per_second: None,
+                    burst: None,
+                    max_concurrent: None,
+                    admin: false,
+                    disabled: false,
+                });
+            }
+        }
+        if configs.is_empty() {
+            return Ok(None);
+        }
+
+        let (mut names, mut secrets) = (HashSet::new(), HashSet::new());
+        for config in &configs {
+            if config.name.is_empty() || !names.insert(config.name.as_str()) {
+                return Err(format!(
+                    "API key names must be unique and non-empty: {:?}",
+                    config.name
+                ));
+            }
+            if config.key.is_empty() || !secrets.insert(config.key.as_str()) {
+                return Err(format!(
+                    "API key {}: key must be unique and non-empty",
+                    config.name
+                ));
+            }
+        }
+        let keys = configs
+            .into_iter()
+            .map(ApiKey::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        info!("Loaded {} API keys", keys.len());
+        Ok(Some(KeyStore { keys }))
+    }
+
+    fn has_admin(&self) -> bool {
+        self.keys.iter().any(|key| key.admin && !key.disabled)
+    }
+
+    // Compares against every key in constant time per key, so timing does not reveal prefixes
+    fn find(&self, token: &str) -> Option<&ApiKey> {
+        self.keys
+            .iter()
+            .find(|key| constant_time_eq(key.key.as_bytes(), token.as_bytes()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+// Checks the bearer key, its access to the route, its concurrency quota and its token bucket,
+// in that order; public paths and deployments without keys skip all of it, except that /admin
+// routes stay closed until an admin key exists
+pub async fn authenticate(request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    let has_admin = API_KEYS.as_ref().is_some_and(KeyStore::has_admin);
+    if path.starts_with("/admin/") && !has_admin {
+        return handle_error(
+            ErrorCode::ApiKeyForbidden,
+            "Admin routes are disabled until an admin API key is configured",
+        )
+        .into_response();
+    }
+    let Some(store) = API_KEYS.as_ref() else {
+        return next.run(request).await;
+    };
+    // Per-key metrics are labelled by key name, so /metrics is an admin route once keys exist
+    let admin_only = path.starts_with("/admin/") || path == METRICS_PATH;
+    if !admin_only && CONFIG.auth.public_paths.iter().any(|public| public == path) {
+        return next.run(request).await;
+    }
+
+    let Some(token) = bearer_token(request.headers()) else {
+        return unauthorized("Missing Authorization: Bearer header");
+    };
+    let Some(key) = store.find(token) else {
+        return unauthorized("Unknown API key");
+    };
+    key.requests.fetch_add(1, Ordering::Relaxed);
+    if key.disabled || (admin_only && !key.admin) {
+        let details = if key.disabled {
+            format!("API key {} is disabled", key.name)
+        } else {
+            format!("API key {} is not an admin key", key.name)
+        };
+        let response = handle_error(ErrorCode::ApiKeyForbidden, details).into_response();
+        key.record(response.status());
+        return response;
+    }
+
+    let (admitted, limit) = key.admit(Instant::now());
+    let mut response = match admitted {
+        Ok(_guard) => next.run(request).await,
+        Err(response) => response,
+    };
+    key.add_rate_limit_headers(&mut response, &limit);
+    key.record(response.status());
+    response
+}
+
+fn unauthorized(details: &str) -> Response {
+    let mut response = handle_error(ErrorCode::Unauthorized, details).into_response();
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+// Per-key counters for billing; an admin route, so only admin keys see it
+pub async fn usage() -> (StatusCode, Json<UsageResponse>) {
+    let keys = API_KEYS
+        .as_ref()
+        .map(|store| store.keys.iter().map(ApiKey::usage).collect())
+        .unwrap_or_default();
+    (
+        StatusCode::OK,
+        Json(UsageResponse {
+            auth_enabled: API_KEYS.is_some(),
+            keys,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn key(requests_per_second: f64, burst: u32, max_concurrent: usize) -> ApiKey {
+        ApiKey::new(KeyConfig {
+            name: "test".to_string(),
+            key: "secret".to_string(),
+            requests_per_second: Some(requests_per_second),
+            burst: Some(burst),
+            max_concurrent: Some(max_concurrent),
+            admin: false,
+            disabled: false,
+        })
+        .unwrap()
+    }
+
+    fn header(response: &Response, name: &str) -> u64 {
+        response.headers()[name].to_str().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn refills_the_bucket_up_to_burst() {
+        let key = key(2.0, 3, 4);
+        let start = key.bucket.lock().unwrap().updated;
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+
+        for remaining in [2, 1, 0] {
+            let limit = key.take_token(at(0.0), true);
+            assert!(limit.allowed);
+            assert_eq!(limit.remaining, remaining);
+        }
+        let limit = key.take_token(at(0.0), true);
+        assert!(!limit.allowed);
+        assert_eq!((limit.reset_secs, limit.retry_after_secs), (2, 1));
+
+        // Two tokens a second
+        let limit = key.take_token(at(1.0), true);
+        assert!(limit.allowed);
+        assert_eq!(limit.remaining, 1);
+
+        // Never more than burst, however long the key was idle
+        let limit = key.take_token(at(60.0), false);
+        assert_eq!((limit.remaining, limit.reset_secs), (3, 0));
+    }
+
+    #[test]
+    fn refuses_an_empty_bucket_with_retry_after() {
+        let key = key(0.5, 1, 4);
+        let now = key.bucket.lock().unwrap().updated;
+        let (admitted, limit) = key.admit(now);
+        assert!(admitted.is_ok());
+        assert_eq!(limit.remaining, 0);
+
+        let (admitted, limit) = key.admit(now);
+        let mut response = admitted.err().unwrap();
+        key.add_rate_limit_headers(&mut response, &limit);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, RETRY_AFTER.as_str()), 2);
+        assert_eq!(header(&response, RATE_LIMIT_LIMIT), 1);
+        assert_eq!(header(&response, RATE_LIMIT_REMAINING), 0);
+        assert_eq!(header(&response, RATE_LIMIT_RESET), 2);
+    }
+
+    #[test]
+    fn refuses_over_the_concurrency_quota_without_taking_a_token() {
+        let key = key(1.0, 5, 1);
+        let now = key.bucket.lock().unwrap().updated;
+        let (first, limit) = key.admit(now);
+        assert!(first.is_ok());
+        assert_eq!(limit.remaining, 4);
+
+        let (second, limit) = key.admit(now);
+        let mut response = second.err().unwrap();
+        key.add_rate_limit_headers(&mut response, &limit);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            header(&response, RETRY_AFTER.as_str()),
+            CONFIG.retry_after_secs
+        );
+        assert_eq!(header(&response, RATE_LIMIT_LIMIT), 5);
+        assert_eq!(header(&response, RATE_LIMIT_REMAINING), 4);
+        assert_eq!(key.in_flight.load(Ordering::Acquire), 1);
+
+        // Finishing the first request frees its slot
+        drop(first);
+        let (third, limit) = key.admit(now);
+        assert!(third.is_ok());
+        assert_eq!(limit.remaining, 3);
+    }
+}
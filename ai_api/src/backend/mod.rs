@@ -0,0 +1,37 @@
+use tch::{Device, Tensor};
+
+use crate::config::{BackendKind, ModelConfig};
+
+pub mod onnx;
+pub mod torchscript;
+
+use onnx::OnnxBackend;
+use torchscript::TorchScriptBackend;
+
+pub trait InferenceBackend: Send + Sync {
+    // Runs a [N, C, H, W] float batch and returns the raw model output, one row per image
+    fn forward(&self, batch: &Tensor) -> Result<Tensor, String>;
+
+    // Runs another exported method of the model, such as a feature extractor
+    fn forward_method(&self, method: &str, _batch: &Tensor) -> Result<Tensor, String> {
+        Err(format!("Backend does not support method {}", method))
+    }
+
+    fn device(&self) -> Device;
+}
+
+pub fn load_backend(
+    config: &ModelConfig,
+    device: Device,
+) -> Result<Box<dyn InferenceBackend>, String> {
+    match config.backend {
+        BackendKind::Torchscript => Ok(Box::new(TorchScriptBackend::load(
+            &config.model_path,
+            device,
+        )?)),
+        BackendKind::Onnx => Ok(Box::new(OnnxBackend::load(
+            &config.model_path,
+            config.preprocess.crop_size,
+        )?)),
+    }
+}
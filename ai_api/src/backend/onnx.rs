@@ -0,0 +1,64 @@
+use tch::{Device, Kind, Tensor};
+use tract_onnx::prelude::{
+    tvec, DatumExt, Framework, InferenceModelExt, Tensor as TractTensor, ToDim, TypedModel,
+    TypedRunnableModel,
+};
+
+use super::InferenceBackend;
+
+// CPU-only backend running ONNX exports through tract, without libtorch kernels
+pub struct OnnxBackend {
+    model: TypedRunnableModel<TypedModel>,
+}
+
+impl OnnxBackend {
+    pub fn load(path: &str, input_size: u32) -> Result<OnnxBackend, String> {
+        let mut model = tract_onnx::onnx()
+            .model_for_path(path)
+            .map_err(|err| err.to_string())?;
+
+        // Keep the batch dimension symbolic so batched requests share one plan
+        let batch = model.sym("N");
+        let size = input_size as usize;
+        let input_fact = f32::fact([batch.to_dim(), 3.to_dim(), size.to_dim(), size.to_dim()]);
+        model
+            .set_input_fact(0, input_fact.into())
+            .map_err(|err| err.to_string())?;
+
+        let model = model
+            .into_optimized()
+            .and_then(|model| model.into_runnable())
+            .map_err(|err| err.to_string())?;
+        Ok(OnnxBackend { model })
+    }
+}
+
+impl InferenceBackend for OnnxBackend {
+    fn forward(&self, batch: &Tensor) -> Result<Tensor, String> {
+        let batch = batch
+            .to_device(Device::Cpu)
+            .to_kind(Kind::Float)
+            .contiguous();
+        let shape: Vec<usize> = batch.size().iter().map(|dim| *dim as usize).collect();
+        let values = Vec::<f32>::try_from(&batch.flatten(0, -1)).map_err(|err| err.to_string())?;
+        let input = TractTensor::from_shape(&shape, &values).map_err(|err| err.to_string())?;
+
+        let outputs = self
+            .model
+            .run(tvec!(input.into()))
+            .map_err(|err| err.to_string())?;
+        let output = outputs
+            .first()
+            .ok_or("ONNX model returned no outputs")?
+            .to_array_view::<f32>()
+            .map_err(|err| err.to_string())?;
+
+        let shape: Vec<i64> = output.shape().iter().map(|dim| *dim as i64).collect();
+        let values: Vec<f32> = output.iter().copied().collect();
+        Ok(Tensor::from_slice(&values).reshape(shape))
+    }
+
+    fn device(&self) -> Device {
+        Device::Cpu
+    }
+}
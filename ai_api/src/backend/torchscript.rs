@@ -0,0 +1,39 @@
+use tch::{CModule, Device, Tensor};
+
+use super::InferenceBackend;
+
+pub struct TorchScriptBackend {
+    module: CModule,
+    device: Device,
+}
+
+impl TorchScriptBackend {
+    pub fn load(path: &str, device: Device) -> Result<TorchScriptBackend, String> {
+        let module = CModule::load_on_device(path, device).map_err(|err| err.to_string())?;
+        Ok(TorchScriptBackend { module, device })
+    }
+}
+
+impl InferenceBackend for TorchScriptBackend {
+    fn forward(&self, batch: &Tensor) -> Result<Tensor, String> {
+        let batch = batch.to_device(self.device);
+        let _guard = tch::no_grad_guard();
+
+        self.module
+            .forward_ts(&[batch])
+            .map_err(|err| err.to_string())
+    }
+
+    fn forward_method(&self, method: &str, batch: &Tensor) -> Result<Tensor, String> {
+        let batch = batch.to_device(self.device);
+        let _guard = tch::no_grad_guard();
+
+        self.module
+            .method_ts(method, &[batch])
+            .map_err(|err| err.to_string())
+    }
+
+    fn device(&self) -> Device {
+        self.device
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Weak;
+use tch::Tensor;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout_at, Duration, Instant};
+use tracing::{debug, error};
+
+use crate::config::CONFIG;
+use crate::errors::ErrorCode;
+use crate::model::LoadedModel;
+use crate::types::ClassScore;
+use crate::workers::WORKERS;
+
+type JobResult = Result<Vec<ClassScore>, (ErrorCode, String)>;
+
+struct BatchJob {
+    tensor: Tensor,
+    top_k: usize,
+    respond_to: oneshot::Sender<JobResult>,
+}
+
+pub struct Batcher {
+    sender: mpsc::UnboundedSender<BatchJob>,
+}
+
+impl Batcher {
+    pub fn spawn(model: Weak<LoadedModel>) -> Batcher {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run_batcher(model, receiver));
+
+        Batcher { sender }
+    }
+
+    // Queues a single-image tensor and waits for its slice of the batched output
+    pub async fn submit(&self, tensor: Tensor, top_k: usize) -> JobResult {
+        let (respond_to, response) = oneshot::channel();
+        self.sender
+            .send(BatchJob {
+                tensor,
+                top_k,
+                respond_to,
+            })
+            .map_err(|err| (ErrorCode::InferenceFailed, err.to_string()))?;
+
+        response
+            .await
+            .map_err(|err| (ErrorCode::InferenceFailed, err.to_string()))?
+    }
+}
+
+async fn run_batcher(model: Weak<LoadedModel>, mut receiver: mpsc::UnboundedReceiver<BatchJob>) {
+    while let Some(job) = receiver.recv().await {
+        let deadline = Instant::now() + Duration::from_millis(CONFIG.batch_window_ms);
+        let mut jobs = vec![job];
+        while jobs.len() < CONFIG.max_batch_size {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(job)) => jobs.push(job),
+                _ => break,
+            }
+        }
+
+        let Some(model) = model.upgrade() else {
+            break;
+        };
+        debug!("Running batch of {} requests on {}", jobs.len(), model.name);
+        if let Err((_, err)) = WORKERS.run(move || run_batch(&model, jobs)).await {
+            error!("Batch inference task failed: {}", err);
+        }
+    }
+}
+
+fn run_batch(model: &LoadedModel, jobs: Vec<BatchJob>) {
+    // Run the batch with the largest requested k, then trim per caller
+    let top_k = jobs.iter().map(|job| job.top_k).max().unwrap_or(1);
+    let (tensors, callers): (Vec<_>, Vec<_>) = jobs
+        .into_iter()
+        .map(|job| (job.tensor, (job.top_k, job.respond_to)))
+        .unzip();
+
+    match model.perform_batch_inference(Tensor::cat(&tensors, 0), top_k) {
+        Ok(predictions) => {
+            for ((top_k, respond_to), mut prediction) in callers.into_iter().zip(predictions) {
+                prediction.truncate(top_k);
+                let _ = respond_to.send(Ok(prediction));
+            }
+        }
+        Err((err_code, err_msg)) => {
+            for (_, respond_to) in callers {
+                let _ = respond_to.send(Err((err_code.clone(), err_msg.clone())));
+            }
+        }
+    }
+}
@@ -1,2 +1,525 @@
-pub const PYTORCH_MODEL_PATH: &str = "./models/resnet18_torchscript.pt";
-pub const IMAGE_CLASS_PATH: &str = "./models/imagenet_classes.txt";
+use image::imageops::FilterType;
+use image::ImageFormat;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use crate::utils::common::env_override;
+
+// The config file is optional unless its path is set explicitly through the environment
+pub const CONFIG_PATH_ENV: &str = "AI_API_CONFIG";
+pub const DEFAULT_CONFIG_PATH: &str = "./config.toml";
+
+lazy_static! {
+    pub static ref CONFIG: AppConfig = AppConfig::load().expect("Failed to load config");
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub bind_address: String,
+    pub model_path: String,
+    pub class_path: String,
+    pub device: String, // "auto", "cpu", "cuda" or "cuda:N"; "auto" falls back to CPU
+    pub log_level: String,
+    pub max_body_bytes: usize,
+    pub max_image_pixels: u64, // Width x height above which images are rejected before decoding
+    pub max_decoder_alloc_bytes: u64, // Memory a decoder may allocate for one image
+    pub max_image_memory_bytes: u64, // Buffers held at once while rotating, flattening and resizing
+    pub decoder_alloc_bytes: HashMap<String, u64>, // Per-format overrides, keyed by extension
+    pub max_batch_images: usize,
+    pub url_allowed_hosts: Vec<String>, // Hosts image_url may point at; "*.example.com" matches subdomains
+    pub url_fetch_timeout_secs: u64,
+    pub url_fetch_max_bytes: usize,
+    pub default_top_k: usize,
+    pub batch_window_ms: u64, // Requests arriving within the window run as one batch
+    pub max_batch_size: usize,
+    pub backend: BackendKind,
+    pub worker_threads: usize, // Threads decoding images and running the models
+    pub worker_queue_size: usize, // Jobs waiting for a worker before requests get 503
+    pub retry_after_secs: u64, // Retry-After sent with 503 responses
+    pub reload_poll_secs: u64, // How often model files are checked for changes; 0 disables
+    pub preprocess: PreprocessConfig,
+    pub rejection: RejectionConfig,
+    pub auth: AuthConfig,
+    pub index_path: Option<String>, // Similarity index file, loaded at startup; in memory only when unset
+    pub index_model: Option<String>, // Embeds images for the index; the first embedding model when unset
+    pub default_model: Option<String>, // Served by /classify; the first model when unset
+    pub models: Vec<ModelConfig>,    // Falls back to a single model from the keys above
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelConfig {
+    pub name: String,
+    pub model_path: String,
+    #[serde(default)]
+    pub class_path: String, // Not needed by embedding models
+    #[serde(default)]
+    pub backend: BackendKind,
+    #[serde(default)]
+    pub task: ModelTask,
+    #[serde(default)]
+    pub embedding_method: Option<String>, // TorchScript method returning features, e.g. "embed"
+    #[serde(default)]
+    pub preprocess: PreprocessConfig,
+    #[serde(default)]
+    pub detection: DetectionConfig, // Only used by detection models
+    #[serde(default)]
+    pub rejection: RejectionConfig, // Only used by classification models
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            bind_address: "0.0.0.0:8000".to_string(),
+            model_path: "./models/resnet18_torchscript.pt".to_string(),
+            class_path: "./models/imagenet_classes.txt".to_string(),
+            device: "auto".to_string(),
+            log_level: "info".to_string(),
+            max_body_bytes: 10 * 1024 * 1024,
+            max_image_pixels: 40_000_000,
+            max_decoder_alloc_bytes: 512 * 1024 * 1024,
+            max_image_memory_bytes: 512 * 1024 * 1024,
+            decoder_alloc_bytes: HashMap::new(),
+            max_batch_images: 256,
+            url_allowed_hosts: Vec::new(),
+            url_fetch_timeout_secs: 10,
+            url_fetch_max_bytes: 10 * 1024 * 1024,
+            default_top_k: 1,
+            batch_window_ms: 5,
+            max_batch_size: 32,
+            backend: BackendKind::default(),
+            worker_threads: thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(4),
+            worker_queue_size: 64,
+            retry_after_secs: 1,
+            reload_poll_secs: 10,
+            preprocess: PreprocessConfig::default(),
+            rejection: RejectionConfig::default(),
+            auth: AuthConfig::default(),
+            index_path: None,
+            index_model: None,
+            default_model: None,
+            models: Vec::new(),
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn load() -> Result<AppConfig, String> {
+        let mut config = match env::var(CONFIG_PATH_ENV) {
+            Ok(path) => AppConfig::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                AppConfig::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            Err(_) => AppConfig::default(),
+        };
+        config.apply_env_overrides()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn model_configs(&self) -> Vec<ModelConfig> {
+        if !self.models.is_empty() {
+            return self.models.clone();
+        }
+        vec![ModelConfig {
+            name: "default".to_string(),
+            model_path: self.model_path.clone(),
+            class_path: self.class_path.clone(),
+            backend: self.backend,
+            task: ModelTask::default(),
+            embedding_method: None,
+            preprocess: self.preprocess.clone(),
+            detection: DetectionConfig::default(),
+            rejection: self.rejection.clone(),
+        }]
+    }
+
+    // Decoder allocation limit for a format, e.g. the "png" or "jpg" entry of decoder_alloc_bytes
+    pub fn decoder_alloc_limit(&self, format: ImageFormat) -> u64 {
+        self.decoder_alloc_bytes
+            .iter()
+            .find(|(extension, _)| ImageFormat::from_extension(extension) == Some(format))
+            .map_or(self.max_decoder_alloc_bytes, |(_, bytes)| *bytes)
+    }
+
+    pub fn default_model_name(&self) -> String {
+        match &self.default_model {
+            Some(name) => name.clone(),
+            None => self.model_configs()[0].name.clone(),
+        }
+    }
+
+    fn from_file(path: &str) -> Result<AppConfig, String> {
+        let content = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        toml::from_str(&content).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<(), String> {
+        env_override("AI_API_BIND_ADDRESS", &mut self.bind_address)?;
+        env_override("AI_API_MODEL_PATH", &mut self.model_path)?;
+        env_override("AI_API_CLASS_PATH", &mut self.class_path)?;
+        env_override("AI_API_DEVICE", &mut self.device)?;
+        env_override("AI_API_LOG_LEVEL", &mut self.log_level)?;
+        env_override("AI_API_MAX_BODY_BYTES", &mut self.max_body_bytes)?;
+        env_override("AI_API_MAX_IMAGE_PIXELS", &mut self.max_image_pixels)?;
+        env_override(
+            "AI_API_MAX_DECODER_ALLOC_BYTES",
+            &mut self.max_decoder_alloc_bytes,
+        )?;
+        env_override(
+            "AI_API_MAX_IMAGE_MEMORY_BYTES",
+            &mut self.max_image_memory_bytes,
+        )?;
+        env_override("AI_API_MAX_BATCH_IMAGES", &mut self.max_batch_images)?;
+        env_override(
+            "AI_API_URL_FETCH_TIMEOUT_SECS",
+            &mut self.url_fetch_timeout_secs,
+        )?;
+        env_override("AI_API_URL_FETCH_MAX_BYTES", &mut self.url_fetch_max_bytes)?;
+        if let Ok(hosts) = env::var("AI_API_URL_ALLOWED_HOSTS") {
+            self.url_allowed_hosts = hosts
+                .split(',')
+                .map(|host| host.trim().to_string())
+                .filter(|host| !host.is_empty())
+                .collect();
+        }
+        env_override("AI_API_DEFAULT_TOP_K", &mut self.default_top_k)?;
+        env_override("AI_API_BATCH_WINDOW_MS", &mut self.batch_window_ms)?;
+        env_override("AI_API_MAX_BATCH_SIZE", &mut self.max_batch_size)?;
+        env_override("AI_API_WORKER_THREADS", &mut self.worker_threads)?;
+        env_override("AI_API_WORKER_QUEUE_SIZE", &mut self.worker_queue_size)?;
+        env_override("AI_API_RETRY_AFTER_SECS", &mut self.retry_after_secs)?;
+        env_override("AI_API_RELOAD_POLL_SECS", &mut self.reload_poll_secs)?;
+        env_override("AI_API_RATE_LIMIT_RPS", &mut self.auth.requests_per_second)?;
+        env_override("AI_API_RATE_LIMIT_BURST", &mut self.auth.burst)?;
+        env_override("AI_API_MAX_CONCURRENT", &mut self.auth.max_concurrent)?;
+        // Model-level settings apply to the single default model and to every [[models]] entry
+        let mut targets = vec![(&mut self.rejection, &mut self.preprocess)];
+        targets.extend(
+            self.models
+                .iter_mut()
+                .map(|model| (&mut model.rejection, &mut model.preprocess)),
+        );
+        for (rejection, preprocess) in targets {
+            env_override("AI_API_MIN_CONFIDENCE", &mut rejection.min_confidence)?;
+            env_override("AI_API_MIN_MARGIN", &mut rejection.min_margin)?;
+            env_override("AI_API_RESIZE_SIZE", &mut preprocess.resize_size)?;
+            env_override("AI_API_CROP_SIZE", &mut preprocess.crop_size)?;
+        }
+        if let Ok(name) = env::var("AI_API_DEFAULT_MODEL") {
+            self.default_model = Some(name);
+        }
+        if let Ok(path) = env::var("AI_API_KEYS_PATH") {
+            self.auth.keys_path = Some(path);
+        }
+        if let Ok(path) = env::var("AI_API_INDEX_PATH") {
+            self.index_path = Some(path);
+        }
+        if let Ok(name) = env::var("AI_API_INDEX_MODEL") {
+            self.index_model = Some(name);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.max_batch_size == 0 || self.max_batch_images == 0 {
+            return Err("max_batch_size and max_batch_images must be non-zero".to_string());
+        }
+        if self.worker_threads == 0 || self.worker_queue_size == 0 {
+            return Err("worker_threads and worker_queue_size must be non-zero".to_string());
+        }
+        if self.max_body_bytes == 0 || self.max_image_pixels == 0 {
+            return Err("max_body_bytes and max_image_pixels must be non-zero".to_string());
+        }
+        if let Some(format) = self
+            .decoder_alloc_bytes
+            .keys()
+            .find(|format| ImageFormat::from_extension(format).is_none())
+        {
+            return Err(format!(
+                "Unknown image format in decoder_alloc_bytes: {}",
+                format
+            ));
+        }
+        let auth = &self.auth;
+        check_rate_limits(
+            "auth",
+            auth.requests_per_second,
+            auth.burst,
+            auth.max_concurrent,
+        )?;
+        let models = self.model_configs();
+        let mut names = HashSet::new();
+        for model in &models {
+            if model.name.is_empty() || !names.insert(model.name.as_str()) {
+                return Err(format!(
+                    "Model names must be unique and non-empty: {:?}",
+                    model.name
+                ));
+            }
+            if model.preprocess.crop_size == 0
+                || model.preprocess.std.contains(&0.0)
+                || model.preprocess.tta_views.is_empty()
+            {
+                return Err(format!(
+                    "Model {}: preprocess crop_size and std must be non-zero and tta_views non-empty",
+                    model.name
+                ));
+            }
+            let views = &model.preprocess.tta_views;
+            let duplicated = views
+                .iter()
+                .enumerate()
+                .any(|(i, view)| views[..i].contains(view));
+            // five_crop already includes the center crop, which would then count twice
+            if duplicated
+                || (views.contains(&TtaView::FiveCrop) && views.contains(&TtaView::CenterCrop))
+            {
+                return Err(format!(
+                    "Model {}: tta_views must not repeat a view or combine five_crop with center_crop",
+                    model.name
+                ));
+            }
+            // Other resize modes already produce crop_size, so all five crops would be identical
+            if views.contains(&TtaView::FiveCrop)
+                && !matches!(model.preprocess.resize_mode, ResizeMode::ShorterSide)
+            {
+                return Err(format!(
+                    "Model {}: tta_views five_crop needs resize_mode shorter_side",
+                    model.name
+                ));
+            }
+            if model.class_path.is_empty() && model.task != ModelTask::Embedding {
+                return Err(format!("Model {}: class_path is required", model.name));
+            }
+            if model.embedding_method.is_some() && model.backend != BackendKind::Torchscript {
+                return Err(format!(
+                    "Model {}: embedding_method needs the torchscript backend",
+                    model.name
+                ));
+            }
+            let rejection = &model.rejection;
+            if !(0.0..=1.0).contains(&rejection.min_confidence)
+                || !(0.0..=1.0).contains(&rejection.min_margin)
+            {
+                return Err(format!(
+                    "Model {}: rejection thresholds must be between 0 and 1",
+                    model.name
+                ));
+            }
+            let detection = &model.detection;
+            if !(0.0..=1.0).contains(&detection.confidence_threshold)
+                || !(0.0..=1.0).contains(&detection.iou_threshold)
+            {
+                return Err(format!(
+                    "Model {}: detection thresholds must be between 0 and 1",
+                    model.name
+                ));
+            }
+            // A center crop hides everything outside it from detectors and segmenters
+            if matches!(model.task, ModelTask::Detection | ModelTask::Segmentation)
+                && matches!(model.preprocess.resize_mode, ResizeMode::ShorterSide)
+            {
+                return Err(format!(
+                    "Model {}: detection and segmentation models need resize_mode exact or letterbox",
+                    model.name
+                ));
+            }
+        }
+        if !names.contains(self.default_model_name().as_str()) {
+            return Err(format!(
+                "Unknown default model: {}",
+                self.default_model_name()
+            ));
+        }
+        if let Some(name) = &self.index_model {
+            let embeds = models.iter().any(|model| {
+                &model.name == name
+                    && (model.task == ModelTask::Embedding || model.embedding_method.is_some())
+            });
+            if !embeds {
+                return Err(format!("Index model {} has no embedding output", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    Torchscript, // tch::CModule on the configured device
+    Onnx, // tract, CPU only
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelTask {
+    #[default]
+    Classification, // Served by the classify routes
+    Detection,    // Served by the detect routes
+    Embedding,    // Feature extractor whose forward output is the embedding
+    Segmentation, // Served by the segment routes; outputs [N, classes, H, W] logits
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectionFormat {
+    Yolov5, // [N, boxes, 5 + classes]: cx, cy, w, h, objectness, class scores
+    #[default]
+    Yolov8, // [N, 4 + classes, boxes]: cx, cy, w, h, class scores
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DetectionConfig {
+    pub output_format: DetectionFormat,
+    pub confidence_threshold: f32, // Boxes scoring below this are dropped before NMS
+    pub iou_threshold: f32,        // Overlap above which NMS suppresses a same-class box
+    pub max_detections: usize,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        DetectionConfig {
+            output_format: DetectionFormat::default(),
+            confidence_threshold: 0.25,
+            iou_threshold: 0.45,
+            max_detections: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RejectionConfig {
+    pub min_confidence: f32, // Top-1 scores below this answer "unknown"; 0 disables
+    pub min_margin: f32,     // Minimum gap between top-1 and top-2 scores; 0 disables
+    pub thresholds_path: Option<String>, // Per-class minimums; defaults to <class file>.thresholds.txt if present
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub keys_path: Option<String>, // TOML file of [[keys]]; auth is off when no keys are configured
+    pub requests_per_second: f64,  // Token bucket refill rate per key
+    pub burst: u32,                // Token bucket size per key
+    pub max_concurrent: usize,     // In-flight requests per key
+    pub public_paths: Vec<String>, // Served without a key; never opens /admin or /metrics
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            keys_path: None,
+            requests_per_second: 10.0,
+            burst: 20,
+            max_concurrent: 4,
+            public_paths: vec!["/health".to_string(), "/ready".to_string()],
+        }
+    }
+}
+
+// Shared by the [auth] defaults and per-key overrides in the keys file
+pub fn check_rate_limits(
+    owner: &str,
+    requests_per_second: f64,
+    burst: u32,
+    max_concurrent: usize,
+) -> Result<(), String> {
+    if !requests_per_second.is_finite()
+        || requests_per_second <= 0.0
+        || burst == 0
+        || max_concurrent == 0
+    {
+        return Err(format!(
+            "{}: requests_per_second, burst and max_concurrent must be positive",
+            owner
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeMode {
+    Exact,       // Stretch straight to crop_size x crop_size
+    ShorterSide, // Scale shorter side to resize_size, then center-crop
+    Letterbox,   // Fit longer side to crop_size and pad, keeping the aspect ratio
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<Interpolation> for FilterType {
+    fn from(interpolation: Interpolation) -> Self {
+        match interpolation {
+            Interpolation::Nearest => FilterType::Nearest,
+            Interpolation::Bilinear => FilterType::Triangle,
+            Interpolation::Bicubic => FilterType::CatmullRom,
+            Interpolation::Gaussian => FilterType::Gaussian,
+            Interpolation::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelOrder {
+    Rgb,
+    Bgr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TtaView {
+    CenterCrop, // The regular preprocessed view
+    Hflip,      // Center crop mirrored horizontally
+    FiveCrop, // Four corner crops plus the center crop; needs resize_mode shorter_side and replaces center_crop
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    pub resize_mode: ResizeMode,
+    pub resize_size: u32,
+    pub crop_size: u32,
+    pub interpolation: Interpolation,
+    pub mean: [f64; 3], // Given in RGB order, applied before channel reordering
+    pub std: [f64; 3],  // Given in RGB order, applied before channel reordering
+    pub channel_order: ChannelOrder,
+    pub background: [u8; 3], // RGB colour transparent pixels are composited over
+    pub tta_views: Vec<TtaView>, // Views averaged when a request asks for tta
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        PreprocessConfig {
+            resize_mode: ResizeMode::ShorterSide,
+            resize_size: 256,
+            crop_size: 224,
+            interpolation: Interpolation::Bilinear,
+            mean: [0.485, 0.456, 0.406],
+            std: [0.229, 0.224, 0.225],
+            channel_order: ChannelOrder::Rgb,
+            background: [255, 255, 255],
+            tta_views: vec![TtaView::CenterCrop, TtaView::Hflip],
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::cmp::Ordering;
+use tch::{Device, Kind, Tensor};
+
+use crate::config::{DetectionConfig, DetectionFormat};
+
+// A box in model-input pixel coordinates, before mapping back to the original image
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    pub class: usize,
+    pub score: f32,
+    pub x_min: f32,
+    pub y_min: f32,
+    pub x_max: f32,
+    pub y_max: f32,
+}
+
+impl Candidate {
+    fn area(&self) -> f32 {
+        (self.x_max - self.x_min).max(0.0) * (self.y_max - self.y_min).max(0.0)
+    }
+
+    fn iou(&self, other: &Candidate) -> f32 {
+        let width = (self.x_max.min(other.x_max) - self.x_min.max(other.x_min)).max(0.0);
+        let height = (self.y_max.min(other.y_max) - self.y_min.max(other.y_min)).max(0.0);
+        let intersection = width * height;
+        let union = self.area() + other.area() - intersection;
+        if union <= 0.0 {
+            0.0
+        } else {
+            intersection / union
+        }
+    }
+}
+
+// Checks a raw output has the layout the configured format expects
+pub fn validate_output(
+    output: &Tensor,
+    format: DetectionFormat,
+    num_classes: usize,
+) -> Result<(), String> {
+    validate_shape(&output.size(), format, num_classes)
+}
+
+fn validate_shape(size: &[i64], format: DetectionFormat, num_classes: usize) -> Result<(), String> {
+    let valid = match (format, size) {
+        (DetectionFormat::Yolov5, [_, _, values]) => *values as usize == num_classes + 5,
+        (DetectionFormat::Yolov8, [_, values, _]) => *values as usize == num_classes + 4,
+        _ => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Detection output shape {:?} does not match {:?} with {} classes",
+            size, format, num_classes
+        ))
+    }
+}
+
+// Decodes the first image of a raw output, then applies the confidence threshold and NMS
+pub fn detect(
+    output: &Tensor,
+    config: &DetectionConfig,
+    num_classes: usize,
+) -> Result<Vec<Candidate>, String> {
+    validate_output(output, config.output_format, num_classes)?;
+
+    // One row per box: cx, cy, w, h, [objectness], class scores
+    let rows = match config.output_format {
+        DetectionFormat::Yolov5 => output.get(0),
+        DetectionFormat::Yolov8 => output.get(0).transpose(0, 1),
+    };
+    let rows = Vec::<Vec<f32>>::try_from(&rows.to_kind(Kind::Float).to_device(Device::Cpu))
+        .map_err(|err| err.to_string())?;
+
+    let candidates = rows
+        .iter()
+        .filter_map(|row| decode_row(row, config))
+        .collect();
+    Ok(non_max_suppression(
+        candidates,
+        config.iou_threshold,
+        config.max_detections,
+    ))
+}
+
+fn decode_row(row: &[f32], config: &DetectionConfig) -> Option<Candidate> {
+    let (objectness, scores) = match config.output_format {
+        DetectionFormat::Yolov5 => (row[4], &row[5..]),
+        DetectionFormat::Yolov8 => (1.0, &row[4..]),
+    };
+    let (class, class_score) = scores
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))?;
+    let score = objectness * class_score;
+    if !score.is_finite() || score < config.confidence_threshold {
+        return None;
+    }
+
+    let (cx, cy, w, h) = (row[0], row[1], row[2], row[3]);
+    Some(Candidate {
+        class,
+        score,
+        x_min: cx - w / 2.0,
+        y_min: cy - h / 2.0,
+        x_max: cx + w / 2.0,
+        y_max: cy + h / 2.0,
+    })
+}
+
+// Greedy per-class NMS: keeps the best box and drops same-class boxes overlapping it
+fn non_max_suppression(
+    mut candidates: Vec<Candidate>,
+    iou_threshold: f32,
+    max_detections: usize,
+) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+
+    let mut kept: Vec<Candidate> = Vec::new();
+    for candidate in candidates {
+        if kept.len() >= max_detections {
+            break;
+        }
+        let suppressed = kept
+            .iter()
+            .any(|other| other.class == candidate.class && other.iou(&candidate) > iou_threshold);
+        if !suppressed {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(class: usize, score: f32, x_min: f32, y_min: f32, size: f32) -> Candidate {
+        Candidate {
+            class,
+            score,
+            x_min,
+            y_min,
+            x_max: x_min + size,
+            y_max: y_min + size,
+        }
+    }
+
+    fn scores(candidates: &[Candidate]) -> Vec<f32> {
+        candidates.iter().map(|candidate| candidate.score).collect()
+    }
+
+    #[test]
+    fn computes_iou() {
+        let a = candidate(0, 1.0, 0.0, 0.0, 10.0);
+        assert_eq!(a.iou(&a), 1.0);
+        // Half overlapping: 50 / (100 + 100 - 50)
+        let b = candidate(0, 1.0, 5.0, 0.0, 10.0);
+        assert!((a.iou(&b) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(a.iou(&candidate(0, 1.0, 20.0, 20.0, 10.0)), 0.0);
+    }
+
+    #[test]
+    fn suppresses_overlapping_boxes_of_the_same_class() {
+        let kept = non_max_suppression(
+            vec![
+                candidate(0, 0.6, 1.0, 1.0, 10.0),
+                candidate(0, 0.9, 0.0, 0.0, 10.0),
+                candidate(0, 0.5, 50.0, 50.0, 10.0),
+            ],
+            0.45,
+            100,
+        );
+        assert_eq!(scores(&kept), vec![0.9, 0.5]);
+    }
+
+    #[test]
+    fn keeps_overlapping_boxes_of_different_classes() {
+        let kept = non_max_suppression(
+            vec![
+                candidate(0, 0.9, 0.0, 0.0, 10.0),
+                candidate(1, 0.8, 0.0, 0.0, 10.0),
+            ],
+            0.45,
+            100,
+        );
+        assert_eq!(scores(&kept), vec![0.9, 0.8]);
+    }
+
+    #[test]
+    fn caps_detections_at_the_best_scores() {
+        let candidates = (0..10)
+            .map(|i| candidate(0, i as f32 / 10.0, i as f32 * 20.0, 0.0, 10.0))
+            .collect();
+        let kept = non_max_suppression(candidates, 0.45, 3);
+        assert_eq!(scores(&kept), vec![0.9, 0.8, 0.7]);
+    }
+
+    #[test]
+    fn decodes_rows_above_the_threshold() {
+        let config = DetectionConfig {
+            output_format: DetectionFormat::Yolov5,
+            confidence_threshold: 0.25,
+            ..DetectionConfig::default()
+        };
+        // cx, cy, w, h, objectness, two class scores
+        let row = [50.0, 40.0, 20.0, 10.0, 0.5, 0.2, 0.8];
+        let box_ = decode_row(&row, &config).unwrap();
+        assert_eq!(box_.class, 1);
+        assert!((box_.score - 0.4).abs() < 1e-6);
+        assert_eq!(
+            [box_.x_min, box_.y_min, box_.x_max, box_.y_max],
+            [40.0, 35.0, 60.0, 45.0]
+        );
+        assert!(decode_row(&[50.0, 40.0, 20.0, 10.0, 0.3, 0.2, 0.8], &config).is_none());
+
+        let config = DetectionConfig {
+            output_format: DetectionFormat::Yolov8,
+            ..config
+        };
+        assert_eq!(decode_row(&row[..6], &config).unwrap().class, 0);
+    }
+
+    #[test]
+    fn rejects_malformed_output_shapes() {
+        assert!(validate_shape(&[1, 8400, 85], DetectionFormat::Yolov5, 80).is_ok());
+        assert!(validate_shape(&[1, 84, 8400], DetectionFormat::Yolov8, 80).is_ok());
+        for (size, format) in [
+            (vec![1, 8400, 84], DetectionFormat::Yolov5),
+            (vec![1, 8400, 84], DetectionFormat::Yolov8),
+            (vec![8400, 85], DetectionFormat::Yolov5),
+        ] {
+            let message = validate_shape(&size, format, 80).unwrap_err();
+            assert!(message.contains("does not match"), "{}", message);
+        }
+    }
+}
@@ -3,14 +3,60 @@ use serde::Serialize;
 use std::fmt;
 use tracing::error;
 
+use crate::metrics::ERRORS;
+use crate::request_id;
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
-    pub error: String,
+    pub error: String,   // Human-readable summary
+    pub code: ErrorCode, // Machine-readable, e.g. "truncated_image"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>, // What went wrong, for client errors only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>, // Also sent as the X-Request-Id header
 }
 
-#[derive(Debug, Serialize)]
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, details: Option<String>) -> ErrorResponse {
+        ErrorResponse {
+            error: code.to_string(),
+            code,
+            details,
+            request_id: request_id::current(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ErrorCode {
+    InvalidRequest,
+    UnsupportedContentType,
     InvalidInputData,
+    UnsupportedImageFormat,
+    TruncatedImage,
+    EmptyImage,
+    ImageTooLarge,
+    ImageDimensionsTooLarge,
+    DecoderLimitExceeded,
+    ImageMemoryLimitExceeded,
+    ImageUrlNotAllowed,
+    ImageFetchFailed,
+    Unauthorized,
+    ApiKeyForbidden,
+    RateLimited,
+    ConcurrencyLimited,
+    ModelNotFound,
+    RouteNotFound,
+    MethodNotAllowed,
+    UnsupportedTask,
+    ModelNotReady,
+    ModelReloadFailed,
+    IndexEntryNotFound,
+    EmbeddingDimensionMismatch,
+    IndexPersistFailed,
+    IndexQueryFailed,
+    ServerBusy,
     InferenceFailed,
     OutputConversionFailed,
 }
@@ -18,22 +64,124 @@ pub enum ErrorCode {
 impl fmt::Display for ErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            ErrorCode::InvalidRequest => write!(f, "Invalid request"),
+            ErrorCode::UnsupportedContentType => write!(f, "Unsupported content type"),
             ErrorCode::InvalidInputData => write!(f, "Invalid input for inference"),
+            ErrorCode::UnsupportedImageFormat => write!(f, "Unsupported or unknown image format"),
+            ErrorCode::TruncatedImage => write!(f, "Image data is truncated"),
+            ErrorCode::EmptyImage => write!(f, "Image has zero width or height"),
+            ErrorCode::ImageTooLarge => write!(f, "Image exceeds the size limit"),
+            ErrorCode::ImageDimensionsTooLarge => write!(f, "Image exceeds the pixel limit"),
+            ErrorCode::DecoderLimitExceeded => {
+                write!(f, "Image needs more decoder memory than allowed")
+            }
+            ErrorCode::ImageMemoryLimitExceeded => {
+                write!(f, "Image needs more memory to preprocess than allowed")
+            }
+            ErrorCode::ImageUrlNotAllowed => write!(f, "Image URL host is not allowed"),
+            ErrorCode::ImageFetchFailed => write!(f, "Failed to fetch image URL"),
+            ErrorCode::Unauthorized => write!(f, "Missing or invalid API key"),
+            ErrorCode::ApiKeyForbidden => write!(f, "API key may not access this route"),
+            ErrorCode::RateLimited => write!(f, "Rate limit exceeded, retry later"),
+            ErrorCode::ConcurrencyLimited => {
+                write!(f, "Too many concurrent requests for this API key")
+            }
+            ErrorCode::ModelNotFound => write!(f, "Model not found"),
+            ErrorCode::RouteNotFound => write!(f, "Route not found"),
+            ErrorCode::MethodNotAllowed => write!(f, "Method not allowed for this route"),
+            ErrorCode::UnsupportedTask => write!(f, "Model does not support this task"),
+            ErrorCode::ModelNotReady => write!(f, "Models are still loading"),
+            ErrorCode::ModelReloadFailed => write!(f, "Failed to reload model"),
+            ErrorCode::IndexEntryNotFound => write!(f, "Index entry not found"),
+            ErrorCode::EmbeddingDimensionMismatch => {
+                write!(f, "Vector dimension does not match the index")
+            }
+            ErrorCode::IndexPersistFailed => write!(f, "Failed to save the index"),
+            ErrorCode::IndexQueryFailed => write!(f, "Failed to query the index"),
+            ErrorCode::ServerBusy => write!(f, "Server is busy, retry later"),
             ErrorCode::InferenceFailed => write!(f, "Failed to run inference"),
             ErrorCode::OutputConversionFailed => write!(f, "Failed to convert inference output"),
         }
     }
 }
 
-pub fn handle_error<T: std::fmt::Debug>(
+impl ErrorCode {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidInputData
+            | ErrorCode::TruncatedImage
+            | ErrorCode::EmptyImage
+            | ErrorCode::UnsupportedTask
+            | ErrorCode::EmbeddingDimensionMismatch => StatusCode::BAD_REQUEST,
+            ErrorCode::UnsupportedImageFormat | ErrorCode::UnsupportedContentType => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            ErrorCode::ImageTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::ImageDimensionsTooLarge
+            | ErrorCode::DecoderLimitExceeded
+            | ErrorCode::ImageMemoryLimitExceeded => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::ImageUrlNotAllowed | ErrorCode::ApiKeyForbidden => StatusCode::FORBIDDEN,
+            ErrorCode::RateLimited | ErrorCode::ConcurrencyLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::ImageFetchFailed => StatusCode::BAD_GATEWAY,
+            ErrorCode::ModelNotFound | ErrorCode::IndexEntryNotFound | ErrorCode::RouteNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::ModelNotReady | ErrorCode::ServerBusy => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::ModelReloadFailed
+            | ErrorCode::IndexPersistFailed
+            | ErrorCode::IndexQueryFailed
+            | ErrorCode::InferenceFailed
+            | ErrorCode::OutputConversionFailed => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl ErrorCode {
+    // Closest code for a rejection raised by an axum extractor
+    pub fn from_status(status: StatusCode) -> ErrorCode {
+        match status {
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::ImageTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorCode::UnsupportedContentType,
+            StatusCode::NOT_FOUND => ErrorCode::RouteNotFound,
+            StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            _ => ErrorCode::InvalidRequest,
+        }
+    }
+
+    // The snake_case name the JSON body carries, so metrics use the same code
+    pub fn name(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+}
+
+// Logs the full error; clients only see the details of their own mistakes (4xx)
+pub fn handle_error<T: fmt::Display>(
     error_code: ErrorCode,
     err: T,
 ) -> (StatusCode, Json<ErrorResponse>) {
-    error!("{:?}", err);
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        Json(ErrorResponse {
-            error: format!("{}", error_code),
-        }),
-    )
+    error!("{:?}: {}", error_code, err);
+    ERRORS.with_label_values(&[error_code.name()]).inc();
+
+    let status = error_code.status_code();
+    let details = status.is_client_error().then(|| err.to_string());
+    (status, Json(ErrorResponse::new(error_code, details)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_codes_as_the_json_body_does() {
+        assert_eq!(ErrorCode::InvalidInputData.name(), "invalid_input_data");
+        let body =
+            serde_json::to_value(ErrorResponse::new(ErrorCode::ImageTooLarge, None)).unwrap();
+        assert_eq!(body["code"], ErrorCode::ImageTooLarge.name());
+    }
 }
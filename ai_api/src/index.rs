@@ -0,0 +1,259 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+use tracing::info;
+
+use crate::config::CONFIG;
+use crate::errors::ErrorCode;
+use crate::types::IndexMatch;
+
+lazy_static! {
+    pub static ref INDEX: EmbeddingIndex =
+        EmbeddingIndex::load(CONFIG.index_path.as_deref()).expect("Failed to load index");
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexData {
+    dimension: Option<usize>, // Fixed by the first inserted vector
+    entries: BTreeMap<String, Vec<f32>>,
+}
+
+// Brute-force cosine similarity over L2-normalized vectors, sized for small catalogs
+pub struct EmbeddingIndex {
+    data: RwLock<IndexData>,
+    path: Option<String>, // Rewritten after every change when set
+}
+
+impl EmbeddingIndex {
+    fn load(path: Option<&str>) -> Result<EmbeddingIndex, String> {
+        let data = match path {
+            Some(path) if Path::new(path).exists() => {
+                let content =
+                    fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+                let data: IndexData =
+                    serde_json::from_str(&content).map_err(|err| format!("{}: {}", path, err))?;
+                info!("Loaded {} index entries from {}", data.entries.len(), path);
+                data
+            }
+            _ => IndexData::default(),
+        };
+
+        Ok(EmbeddingIndex {
+            data: RwLock::new(data),
+            path: path.map(str::to_string),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.read().unwrap().entries.len()
+    }
+
+    pub fn dimension(&self) -> Option<usize> {
+        self.data.read().unwrap().dimension
+    }
+
+    pub fn get(&self, id: &str) -> Option<Vec<f32>> {
+        self.data.read().unwrap().entries.get(id).cloned()
+    }
+
+    // Inserts or replaces an entry; returns whether the id already existed. Nothing changes
+    // in memory unless the new snapshot was written.
+    pub fn insert(&self, id: String, vector: Vec<f32>) -> Result<bool, (ErrorCode, String)> {
+        let vector = normalize(vector)?;
+        let mut data = self.data.write().unwrap();
+        check_dimension(data.dimension, &vector)?;
+        let dimension = data.dimension.replace(vector.len());
+        let previous = data.entries.insert(id.clone(), vector);
+
+        if let Err(err) = self.save(&data) {
+            data.dimension = dimension;
+            match previous {
+                Some(previous) => data.entries.insert(id, previous),
+                None => data.entries.remove(&id),
+            };
+            return Err(err);
+        }
+        Ok(previous.is_some())
+    }
+
+    pub fn remove(&self, id: &str) -> Result<(), (ErrorCode, String)> {
+        let mut data = self.data.write().unwrap();
+        let Some(previous) = data.entries.remove(id) else {
+            return Err((ErrorCode::IndexEntryNotFound, id.to_string()));
+        };
+        let dimension = data.dimension;
+        if data.entries.is_empty() {
+            data.dimension = None;
+        }
+
+        if let Err(err) = self.save(&data) {
+            data.dimension = dimension;
+            data.entries.insert(id.to_string(), previous);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Returns the k most similar entries, best first, skipping the excluded id
+    pub fn query(
+        &self,
+        vector: Vec<f32>,
+        k: usize,
+        exclude: Option<&str>,
+    ) -> Result<Vec<IndexMatch>, (ErrorCode, String)> {
+        let vector = normalize(vector)?;
+        let data = self.data.read().unwrap();
+        check_dimension(data.dimension, &vector)?;
+
+        let mut matches: Vec<IndexMatch> = data
+            .entries
+            .iter()
+            .filter(|(id, _)| Some(id.as_str()) != exclude)
+            .map(|(id, entry)| IndexMatch {
+                id: id.clone(),
+                score: entry.iter().zip(&vector).map(|(a, b)| a * b).sum(),
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        matches.truncate(k);
+        Ok(matches)
+    }
+
+    // Writes a snapshot to a temporary file and renames it, so a crash never leaves half a file.
+    // Called with the write lock held, which also keeps concurrent saves in order.
+    fn save(&self, data: &IndexData) -> Result<(), (ErrorCode, String)> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = serde_json::to_string(data)
+            .map_err(|err| (ErrorCode::IndexPersistFailed, err.to_string()))?;
+
+        let tmp_path = format!("{}.tmp", path);
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| (ErrorCode::IndexPersistFailed, err.to_string()))?;
+        }
+        fs::write(&tmp_path, content)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|err| (ErrorCode::IndexPersistFailed, format!("{}: {}", path, err)))
+    }
+}
+
+fn check_dimension(dimension: Option<usize>, vector: &[f32]) -> Result<(), (ErrorCode, String)> {
+    match dimension {
+        Some(dimension) if dimension != vector.len() => Err((
+            ErrorCode::EmbeddingDimensionMismatch,
+            format!("Expected {} values, got {}", dimension, vector.len()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn normalize(mut vector: Vec<f32>) -> Result<Vec<f32>, (ErrorCode, String)> {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if vector.is_empty() || !norm.is_finite() || norm == 0.0 {
+        return Err((
+            ErrorCode::InvalidInputData,
+            "Vector must be non-empty, finite and non-zero".to_string(),
+        ));
+    }
+    vector.iter_mut().for_each(|value| *value /= norm);
+    Ok(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    // A fresh directory under the system temp dir, unique per test and process
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ai_api_index_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ids(matches: &[IndexMatch]) -> Vec<&str> {
+        matches.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[test]
+    fn ranks_by_cosine_similarity() {
+        let index = EmbeddingIndex::load(None).unwrap();
+        index.insert("east".to_string(), vec![2.0, 0.0]).unwrap();
+        index.insert("north".to_string(), vec![0.0, 5.0]).unwrap();
+        index
+            .insert("north_east".to_string(), vec![1.0, 1.0])
+            .unwrap();
+
+        let matches = index.query(vec![3.0, 1.0], 3, None).unwrap();
+        assert_eq!(ids(&matches), vec!["east", "north_east", "north"]);
+        assert!((matches[0].score - 3.0 / 10f32.sqrt()).abs() < 1e-6);
+
+        let matches = index.query(vec![3.0, 1.0], 1, Some("east")).unwrap();
+        assert_eq!(ids(&matches), vec!["north_east"]);
+    }
+
+    #[test]
+    fn rejects_mismatched_and_degenerate_vectors() {
+        let index = EmbeddingIndex::load(None).unwrap();
+        index.insert("a".to_string(), vec![1.0, 0.0]).unwrap();
+        let (error_code, _) = index.insert("b".to_string(), vec![1.0; 3]).unwrap_err();
+        assert_eq!(error_code, ErrorCode::EmbeddingDimensionMismatch);
+        let (error_code, _) = index.query(vec![1.0; 3], 1, None).unwrap_err();
+        assert_eq!(error_code, ErrorCode::EmbeddingDimensionMismatch);
+        let (error_code, _) = index.query(vec![0.0, 0.0], 1, None).unwrap_err();
+        assert_eq!(error_code, ErrorCode::InvalidInputData);
+
+        // The dimension is free again once the index is empty
+        index.remove("a").unwrap();
+        index.insert("b".to_string(), vec![1.0; 3]).unwrap();
+        assert_eq!(index.dimension(), Some(3));
+    }
+
+    #[test]
+    fn rolls_back_changes_that_fail_to_persist() {
+        let dir = temp_dir("rollback");
+        let path = dir.join("index.json");
+        let path = path.to_str().unwrap();
+        let index = EmbeddingIndex::load(Some(path)).unwrap();
+        index.insert("a".to_string(), vec![1.0, 0.0]).unwrap();
+        index.insert("b".to_string(), vec![0.0, 1.0]).unwrap();
+
+        // A directory where the temporary snapshot goes makes every save fail, even as root
+        let blocker = format!("{}.tmp", path);
+        fs::create_dir(&blocker).unwrap();
+        let (error_code, _) = index.insert("c".to_string(), vec![1.0, 1.0]).unwrap_err();
+        assert_eq!(error_code, ErrorCode::IndexPersistFailed);
+        assert!(index.insert("a".to_string(), vec![0.0, 1.0]).is_err());
+        assert!(index.remove("b").is_err());
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get("c"), None);
+        assert_eq!(index.get("a"), Some(vec![1.0, 0.0]));
+        assert_eq!(index.get("b"), Some(vec![0.0, 1.0]));
+
+        let empty = EmbeddingIndex::load(Some(&format!("{}/empty.json", dir.display()))).unwrap();
+        fs::create_dir(dir.join("empty.json.tmp")).unwrap();
+        assert!(empty.insert("a".to_string(), vec![1.0; 4]).is_err());
+        assert_eq!((empty.len(), empty.dimension()), (0, None));
+
+        // The snapshot on disk still holds the last successful state
+        fs::remove_dir(&blocker).unwrap();
+        let reloaded = EmbeddingIndex::load(Some(path)).unwrap();
+        assert_eq!(reloaded.len(), 2);
+        assert_eq!(reloaded.dimension(), Some(2));
+        assert_eq!(reloaded.get("a"), Some(vec![1.0, 0.0]));
+        reloaded.remove("a").unwrap();
+        let reloaded = EmbeddingIndex::load(Some(path)).unwrap();
+        assert_eq!(reloaded.len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,22 +1,76 @@
-use axum::{routing::post, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post},
+    Router,
+};
 use tokio::net::TcpListener;
 use tracing::{info, Level};
 
+mod auth;
+mod backend;
+mod batcher;
 mod config;
+mod detection;
 mod errors;
+mod index;
+mod metrics;
 mod model;
+mod registry;
+mod request_id;
 mod routes;
+mod segmentation;
 mod types;
 mod utils;
+mod workers;
 
-use routes::classify;
+use config::CONFIG;
+use routes::{
+    add_retry_after, classify, classify_batch, classify_model, classify_model_batch, detect,
+    detect_model, embed, embed_model, health_check, index_delete, index_info, index_insert,
+    index_query, info, list_models, method_not_allowed, ready_check, reload_model, route_not_found,
+    segment, segment_model,
+};
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+    let log_level: Level = CONFIG.log_level.parse().expect("Invalid log level");
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+    lazy_static::initialize(&auth::API_KEYS);
+    lazy_static::initialize(&index::INDEX);
 
-    let app = Router::new().route("/classify", post(classify));
-    let listener = TcpListener::bind("0.0.0.0:8000").await.unwrap();
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .route("/ready", get(ready_check))
+        .route("/info", get(info))
+        .route("/classify", post(classify))
+        .route("/classify/batch", post(classify_batch))
+        .route("/detect", post(detect))
+        .route("/embed", post(embed))
+        .route("/index", get(index_info))
+        .route("/index/items", post(index_insert))
+        .route("/index/items/{id}", delete(index_delete))
+        .route("/index/query", post(index_query))
+        .route("/segment", post(segment))
+        .route("/models", get(list_models))
+        .route("/models/{name}/classify", post(classify_model))
+        .route("/models/{name}/classify/batch", post(classify_model_batch))
+        .route("/models/{name}/detect", post(detect_model))
+        .route("/models/{name}/embed", post(embed_model))
+        .route("/models/{name}/segment", post(segment_model))
+        .route("/admin/models/{name}/reload", post(reload_model))
+        .route("/admin/usage", get(auth::usage))
+        .route("/metrics", get(metrics::metrics))
+        .fallback(route_not_found)
+        .method_not_allowed_fallback(method_not_allowed)
+        .layer(middleware::map_response(add_retry_after))
+        .layer(middleware::from_fn(auth::authenticate))
+        .layer(middleware::from_fn(metrics::track_requests))
+        .layer(DefaultBodyLimit::max(CONFIG.max_body_bytes))
+        .layer(middleware::from_fn(request_id::assign_request_id));
+    let listener = TcpListener::bind(&CONFIG.bind_address).await.unwrap();
+
+    tokio::spawn(registry::initialize());
 
     info!("AI API server ready!");
     axum::serve(listener, app).await.unwrap();
@@ -0,0 +1,107 @@
+use axum::extract::{MatchedPath, Request};
+use axum::http::{header::CONTENT_TYPE, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec, register_int_gauge,
+    Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+
+// Buckets from 1ms to ~10s, covering both CPU and GPU inference
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ai_api_http_requests_total",
+        "HTTP requests by route and status code",
+        &["route", "status"]
+    )
+    .unwrap();
+    pub static ref IN_FLIGHT_REQUESTS: IntGauge = register_int_gauge!(
+        "ai_api_in_flight_requests",
+        "HTTP requests currently being served"
+    )
+    .unwrap();
+    pub static ref API_KEY_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ai_api_key_requests_total",
+        "Authenticated requests by API key name and status code",
+        &["key", "status"]
+    )
+    .unwrap();
+    pub static ref ERRORS: IntCounterVec = register_int_counter_vec!(
+        "ai_api_errors_total",
+        "Errors returned to clients by error code",
+        &["code"]
+    )
+    .unwrap();
+    pub static ref DECODE_SECONDS: Histogram = register_histogram!(
+        "ai_api_decode_seconds",
+        "Time spent decoding uploaded images",
+        LATENCY_BUCKETS.to_vec()
+    )
+    .unwrap();
+    pub static ref PREPROCESS_SECONDS: Histogram = register_histogram!(
+        "ai_api_preprocess_seconds",
+        "Time spent resizing and normalizing decoded images",
+        LATENCY_BUCKETS.to_vec()
+    )
+    .unwrap();
+    pub static ref FORWARD_SECONDS: HistogramVec = register_histogram_vec!(
+        "ai_api_forward_seconds",
+        "Time spent in the model forward pass by model",
+        &["model"],
+        LATENCY_BUCKETS.to_vec()
+    )
+    .unwrap();
+}
+
+// Decrements the gauge when dropped, including when a client disconnects mid-request
+struct InFlightGuard;
+
+impl InFlightGuard {
+    fn new() -> InFlightGuard {
+        IN_FLIGHT_REQUESTS.inc();
+        InFlightGuard
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT_REQUESTS.dec();
+    }
+}
+
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = {
+        let _in_flight = InFlightGuard::new();
+        next.run(request).await
+    };
+
+    HTTP_REQUESTS
+        .with_label_values(&[route.as_str(), response.status().as_str()])
+        .inc();
+    response
+}
+
+pub async fn metrics() -> Response {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => (
+            StatusCode::OK,
+            [(CONTENT_TYPE, encoder.format_type().to_string())],
+            buffer,
+        )
+            .into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
@@ -1,52 +1,662 @@
 use axum::{http::StatusCode, Json};
+use base64::prelude::*;
 use lazy_static::lazy_static;
 use std::sync::Arc;
-use tch::{CModule, Device, Tensor};
+use std::time::SystemTime;
+use tch::{Device, Kind, Tensor};
+use tracing::info;
 
-use crate::config::{IMAGE_CLASS_PATH, PYTORCH_MODEL_PATH};
+use crate::backend::{load_backend, InferenceBackend};
+use crate::batcher::Batcher;
+use crate::config::{
+    BackendKind, DetectionConfig, ModelConfig, ModelTask, PreprocessConfig, RejectionConfig, CONFIG,
+};
+use crate::detection::{detect, validate_output};
 use crate::errors::{handle_error, ErrorCode, ErrorResponse};
-use crate::utils::classes::load_classes;
-use crate::utils::image::preprocess_image;
+use crate::metrics::FORWARD_SECONDS;
+use crate::segmentation;
+use crate::types::{
+    BoundingBox, ClassPolygons, ClassRle, ClassScore, Detection, DetectionResponse,
+    ImagePrediction, MaskFormat, SegmentClass, SegmentationMask, SegmentationResponse,
+};
+use crate::utils::classes::{load_class_thresholds, load_classes, thresholds_path};
+use crate::utils::common::latest_modified;
+use crate::utils::device::parse_device;
+use crate::utils::image::{preprocess_image, preprocess_image_with_transform, preprocess_views};
+use crate::workers::WORKERS;
+
+// Label returned instead of a class when a prediction is rejected
+pub const UNKNOWN_LABEL: &str = "unknown";
+
+pub type BatchItemOutput = Result<ImagePrediction, ErrorResponse>;
 
 lazy_static! {
-    pub static ref MODEL: Arc<CModule> = {
-        let model = CModule::load_on_device(PYTORCH_MODEL_PATH, Device::Cuda(0))
-            .expect("Failed to load Torch model");
+    pub static ref DEVICE: Device = {
+        let device = parse_device(&CONFIG.device).expect("Failed to select device");
+        info!("Using device: {:?}", device);
 
-        Arc::new(model)
+        device
     };
-    pub static ref CLASSES: Vec<String> =
-        load_classes(IMAGE_CLASS_PATH).expect("Failed to load class file");
 }
 
-pub fn perform_inference(tensor: Tensor) -> Result<String, (ErrorCode, String)> {
-    let device = Device::Cuda(0);
-    let tensor = tensor.to_device(device);
-    let _guard = tch::no_grad_guard();
+pub struct LoadedModel {
+    pub name: String,
+    pub model_path: String,
+    pub class_path: String,
+    pub preprocess: PreprocessConfig,
+    pub classes: Vec<String>,
+    pub backend_kind: BackendKind,
+    pub task: ModelTask,
+    pub embedding_method: Option<String>,
+    pub detection: DetectionConfig,
+    pub rejection: RejectionConfig,
+    pub thresholds_path: Option<String>,
+    pub class_thresholds: Vec<Option<f32>>, // Per-class minimum scores, by class index
+    pub modified: Option<SystemTime>,
+    backend: Box<dyn InferenceBackend>,
+    batcher: Batcher,
+}
+
+impl LoadedModel {
+    pub fn load(config: &ModelConfig) -> Result<Arc<LoadedModel>, String> {
+        let thresholds_path = thresholds_path(
+            &config.class_path,
+            config.rejection.thresholds_path.as_deref(),
+        );
+        let modified = latest_modified(&[
+            &config.model_path,
+            &config.class_path,
+            thresholds_path.as_deref().unwrap_or_default(),
+        ]);
+        let backend = load_backend(config, *DEVICE)
+            .map_err(|err| format!("{}: {}", config.model_path, err))?;
+        let classes = if config.class_path.is_empty() {
+            Vec::new()
+        } else {
+            load_classes(&config.class_path)
+                .map_err(|err| format!("{}: {}", config.class_path, err))?
+        };
+        // top_k and the output shape checks need at least one class
+        if classes.is_empty() && config.task != ModelTask::Embedding {
+            return Err(format!(
+                "{}: class file lists no classes",
+                config.class_path
+            ));
+        }
+        let class_thresholds = match &thresholds_path {
+            Some(path) => {
+                load_class_thresholds(path, &classes).map_err(|err| format!("{}: {}", path, err))?
+            }
+            None => vec![None; classes.len()],
+        };
+        info!("Loaded model {} from {}", config.name, config.model_path);
+
+        // The batcher only holds a weak reference so dropping the model stops it
+        Ok(Arc::new_cyclic(|model| LoadedModel {
+            name: config.name.clone(),
+            model_path: config.model_path.clone(),
+            class_path: config.class_path.clone(),
+            preprocess: config.preprocess.clone(),
+            classes,
+            backend_kind: config.backend,
+            task: config.task,
+            embedding_method: config.embedding_method.clone(),
+            detection: config.detection.clone(),
+            rejection: config.rejection.clone(),
+            thresholds_path,
+            class_thresholds,
+            modified,
+            backend,
+            batcher: Batcher::spawn(model.clone()),
+        }))
+    }
+
+    pub fn model_config(&self) -> ModelConfig {
+        ModelConfig {
+            name: self.name.clone(),
+            model_path: self.model_path.clone(),
+            class_path: self.class_path.clone(),
+            backend: self.backend_kind,
+            task: self.task,
+            embedding_method: self.embedding_method.clone(),
+            preprocess: self.preprocess.clone(),
+            detection: self.detection.clone(),
+            rejection: self.rejection.clone(),
+        }
+    }
+
+    // Runs a blank input through the model and checks the output matches the class list
+    pub fn warm_up(&self) -> Result<(), String> {
+        let size = self.preprocess.crop_size as i64;
+        let input = Tensor::zeros([1, 3, size, size], (Kind::Float, Device::Cpu));
+        if self.supports_embedding() {
+            if self.embed(&input)?.numel() == 0 {
+                return Err("Model returned an empty embedding".to_string());
+            }
+            // embed already ran the forward pass of embedding models
+            if self.task == ModelTask::Embedding {
+                return Ok(());
+            }
+        }
+        let output = self.backend.forward(&input)?;
+        match self.task {
+            ModelTask::Classification | ModelTask::Embedding => {}
+            ModelTask::Detection => {
+                return validate_output(&output, self.detection.output_format, self.classes.len())
+            }
+            ModelTask::Segmentation => {
+                return segmentation::validate_output(&output, self.classes.len())
+            }
+        }
+        match output.size().last() {
+            Some(&num_classes) if num_classes as usize == self.classes.len() => Ok(()),
+            _ => Err(format!(
+                "Model output shape {:?} does not match {} classes",
+                output.size(),
+                self.classes.len()
+            )),
+        }
+    }
+
+    // Files whose changes trigger a reload
+    pub fn watched_paths(&self) -> Vec<&str> {
+        let mut paths = vec![self.model_path.as_str(), self.class_path.as_str()];
+        paths.extend(self.thresholds_path.as_deref());
+        paths
+    }
+
+    // The margin rule needs the runner-up score even when the caller asked for top-1
+    pub fn scores_needed(&self, top_k: usize) -> usize {
+        if self.rejection.min_margin > 0.0 {
+            top_k.max(2)
+        } else {
+            top_k
+        }
+    }
 
-    let output = MODEL
-        .forward_ts(&[tensor])
-        .map_err(|err| (ErrorCode::InferenceFailed, err.to_string()))?;
+    // Applies the rejection rules to best-first scores, then trims them to top_k
+    pub fn to_prediction(&self, mut scores: Vec<ClassScore>, top_k: usize) -> ImagePrediction {
+        let rejection = rejection_reason(&self.rejection, &self.class_thresholds, &scores);
+        scores.truncate(top_k.max(1));
+        let mut prediction = ImagePrediction::from(scores);
+        if let Some(reason) = rejection {
+            prediction.label = UNKNOWN_LABEL.to_string();
+            prediction.rejection = Some(reason.to_string());
+        }
+        prediction
+    }
+
+    pub fn device(&self) -> Device {
+        self.backend.device()
+    }
 
-    let probs = output.softmax(-1, tch::Kind::Float);
-    let max_idx = probs.argmax(-1, false).int64_value(&[]);
-    drop(output);
-    drop(probs);
-    if (max_idx as usize) < CLASSES.len() {
-        return Ok(CLASSES[max_idx as usize].clone());
+    pub fn supports_embedding(&self) -> bool {
+        self.task == ModelTask::Embedding || self.embedding_method.is_some()
+    }
+
+    // Runs the feature extractor and L2-normalizes each row of the flattened output
+    fn embed(&self, batch: &Tensor) -> Result<Tensor, String> {
+        let output = match &self.embedding_method {
+            Some(method) => self.backend.forward_method(method, batch)?,
+            None => self.backend.forward(batch)?,
+        };
+        let output = output.to_kind(Kind::Float).flatten(1, -1);
+        let norm = output
+            .square()
+            .sum_dim_intlist([1i64].as_slice(), true, Kind::Float)
+            .sqrt()
+            .clamp_min(1e-12);
+        Ok(output / norm)
+    }
+
+    pub fn perform_embedding(&self, image_bytes: Vec<u8>) -> Result<Vec<f32>, (ErrorCode, String)> {
+        let tensor = preprocess_image(image_bytes, &self.preprocess)?;
+
+        let timer = FORWARD_SECONDS
+            .with_label_values(&[self.name.as_str()])
+            .start_timer();
+        let embedding = self
+            .embed(&tensor)
+            .map_err(|err| (ErrorCode::InferenceFailed, err))?;
+        timer.observe_duration();
+
+        Vec::<f32>::try_from(&embedding.get(0).to_device(Device::Cpu))
+            .map_err(|err| (ErrorCode::OutputConversionFailed, err.to_string()))
+    }
+
+    pub fn perform_batch_inference(
+        &self,
+        batch: Tensor,
+        top_k: usize,
+    ) -> Result<Vec<Vec<ClassScore>>, (ErrorCode, String)> {
+        let timer = FORWARD_SECONDS
+            .with_label_values(&[self.name.as_str()])
+            .start_timer();
+        let output = self
+            .backend
+            .forward(&batch)
+            .map_err(|err| (ErrorCode::InferenceFailed, err))?;
+        timer.observe_duration();
+
+        let probs = output.softmax(-1, Kind::Float);
+        drop(output);
+        self.top_k_scores(probs, top_k)
+    }
+
+    // Runs every augmented view as one batch and averages their softmax outputs
+    pub fn perform_tta(
+        &self,
+        image_bytes: Vec<u8>,
+        top_k: usize,
+    ) -> Result<(Vec<ClassScore>, Vec<String>), (ErrorCode, String)> {
+        let (batch, views) =
+            preprocess_views(image_bytes, &self.preprocess, &self.preprocess.tta_views)?;
+
+        let timer = FORWARD_SECONDS
+            .with_label_values(&[self.name.as_str()])
+            .start_timer();
+        let output = self
+            .backend
+            .forward(&batch)
+            .map_err(|err| (ErrorCode::InferenceFailed, err))?;
+        timer.observe_duration();
+
+        let probs = output
+            .softmax(-1, Kind::Float)
+            .mean_dim(0, true, Kind::Float);
+        drop(output);
+        let predictions = self.top_k_scores(probs, top_k)?.into_iter().next().ok_or((
+            ErrorCode::OutputConversionFailed,
+            "Empty model output".to_string(),
+        ))?;
+        Ok((predictions, views))
+    }
+
+    fn top_k_scores(
+        &self,
+        probs: Tensor,
+        top_k: usize,
+    ) -> Result<Vec<Vec<ClassScore>>, (ErrorCode, String)> {
+        let top_k = top_k.clamp(1, self.classes.len()) as i64;
+        let (scores, indices) = probs.topk(top_k, -1, true, true);
+        drop(probs);
+
+        let scores = Vec::<Vec<f32>>::try_from(&scores.to_device(Device::Cpu))
+            .map_err(|err| (ErrorCode::OutputConversionFailed, err.to_string()))?;
+        let indices = Vec::<Vec<i64>>::try_from(&indices.to_device(Device::Cpu))
+            .map_err(|err| (ErrorCode::OutputConversionFailed, err.to_string()))?;
+
+        indices
+            .into_iter()
+            .zip(scores)
+            .map(|(indices, scores)| self.to_class_scores(indices, scores))
+            .collect()
+    }
+
+    // Detection runs one image at a time, outside the classification batcher
+    pub fn perform_detection(
+        &self,
+        image_bytes: Vec<u8>,
+    ) -> Result<DetectionResponse, (ErrorCode, String)> {
+        let (tensor, transform) = preprocess_image_with_transform(image_bytes, &self.preprocess)?;
+
+        let timer = FORWARD_SECONDS
+            .with_label_values(&[self.name.as_str()])
+            .start_timer();
+        let output = self
+            .backend
+            .forward(&tensor)
+            .map_err(|err| (ErrorCode::InferenceFailed, err))?;
+        timer.observe_duration();
+
+        let candidates = detect(&output, &self.detection, self.classes.len())
+            .map_err(|err| (ErrorCode::OutputConversionFailed, err))?;
+        let detections = candidates
+            .into_iter()
+            .map(|candidate| {
+                let label = self.classes.get(candidate.class).cloned().ok_or((
+                    ErrorCode::OutputConversionFailed,
+                    "Class Index Out of Bound".to_string(),
+                ))?;
+                let (x_min, y_min) = transform.to_original(candidate.x_min, candidate.y_min);
+                let (x_max, y_max) = transform.to_original(candidate.x_max, candidate.y_max);
+                Ok(Detection {
+                    index: candidate.class as i64,
+                    label,
+                    score: candidate.score,
+                    bbox: BoundingBox {
+                        x_min,
+                        y_min,
+                        x_max,
+                        y_max,
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(DetectionResponse {
+            width: transform.width,
+            height: transform.height,
+            detections,
+        })
+    }
+
+    // Segmentation runs one image at a time, outside the classification batcher
+    pub fn perform_segmentation(
+        &self,
+        image_bytes: Vec<u8>,
+        format: MaskFormat,
+    ) -> Result<SegmentationResponse, (ErrorCode, String)> {
+        let (tensor, transform) = preprocess_image_with_transform(image_bytes, &self.preprocess)?;
+
+        let timer = FORWARD_SECONDS
+            .with_label_values(&[self.name.as_str()])
+            .start_timer();
+        let output = self
+            .backend
+            .forward(&tensor)
+            .map_err(|err| (ErrorCode::InferenceFailed, err))?;
+        timer.observe_duration();
+
+        let mask = segmentation::class_mask(
+            &output,
+            self.classes.len(),
+            self.preprocess.crop_size,
+            transform,
+        )
+        .map_err(|err| (ErrorCode::OutputConversionFailed, err))?;
+
+        let classes: Vec<SegmentClass> = mask
+            .class_counts()
+            .into_iter()
+            .map(|(index, pixels)| SegmentClass {
+                index: index as i64,
+                label: self.classes[index as usize].clone(),
+                pixels,
+            })
+            .collect();
+        let data = match format {
+            MaskFormat::Png => {
+                let png = mask
+                    .to_png()
+                    .map_err(|err| (ErrorCode::OutputConversionFailed, err))?;
+                SegmentationMask::Png(BASE64_STANDARD.encode(png))
+            }
+            MaskFormat::Rle => SegmentationMask::Rle(
+                classes
+                    .iter()
+                    .map(|class| ClassRle {
+                        index: class.index,
+                        label: class.label.clone(),
+                        size: [mask.height, mask.width],
+                        counts: mask.to_rle(class.index as u8),
+                    })
+                    .collect(),
+            ),
+            MaskFormat::Polygons => SegmentationMask::Polygons(
+                classes
+                    .iter()
+                    .map(|class| ClassPolygons {
+                        index: class.index,
+                        label: class.label.clone(),
+                        polygons: mask.to_polygons(class.index as u8),
+                    })
+                    .collect(),
+            ),
+        };
+
+        Ok(SegmentationResponse {
+            width: mask.width,
+            height: mask.height,
+            format,
+            classes,
+            mask: data,
+        })
+    }
+
+    fn to_class_scores(
+        &self,
+        indices: Vec<i64>,
+        scores: Vec<f32>,
+    ) -> Result<Vec<ClassScore>, (ErrorCode, String)> {
+        indices
+            .into_iter()
+            .zip(scores)
+            .map(|(index, score)| match self.classes.get(index as usize) {
+                Some(label) => Ok(ClassScore {
+                    index,
+                    label: label.clone(),
+                    score,
+                }),
+                None => Err((
+                    ErrorCode::OutputConversionFailed,
+                    "Class Index Out of Bound".to_string(),
+                )),
+            })
+            .collect()
     }
-    Err((
-        ErrorCode::OutputConversionFailed,
-        "Class Index Out of Bound".to_string(),
-    ))
 }
-pub fn run_classification(
+
+pub async fn run_classification(
+    model: &LoadedModel,
     image_bytes: Vec<u8>,
-) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
-    let tensor = preprocess_image(image_bytes)
-        .map_err(|err| handle_error(ErrorCode::InvalidInputData, err))?;
+    top_k: usize,
+) -> Result<ImagePrediction, (StatusCode, Json<ErrorResponse>)> {
+    let preprocess = model.preprocess.clone();
+    let tensor = WORKERS
+        .try_run(move || preprocess_image(image_bytes, &preprocess))
+        .await
+        .and_then(|tensor| tensor)
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))?;
+
+    let scores = model
+        .batcher
+        .submit(tensor, model.scores_needed(top_k))
+        .await
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))?;
+    Ok(model.to_prediction(scores, top_k))
+}
+
+pub async fn run_tta_classification(
+    model: Arc<LoadedModel>,
+    image_bytes: Vec<u8>,
+    top_k: usize,
+) -> Result<ImagePrediction, (StatusCode, Json<ErrorResponse>)> {
+    let (scores, views) = WORKERS
+        .try_run({
+            let model = model.clone();
+            move || model.perform_tta(image_bytes, model.scores_needed(top_k))
+        })
+        .await
+        .and_then(|result| result)
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))?;
+
+    let mut prediction = model.to_prediction(scores, top_k);
+    prediction.augmentations = Some(views);
+    Ok(prediction)
+}
+
+pub async fn run_detection(
+    model: Arc<LoadedModel>,
+    image_bytes: Vec<u8>,
+) -> Result<DetectionResponse, (StatusCode, Json<ErrorResponse>)> {
+    WORKERS
+        .try_run(move || model.perform_detection(image_bytes))
+        .await
+        .and_then(|result| result)
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))
+}
+
+pub async fn run_segmentation(
+    model: Arc<LoadedModel>,
+    image_bytes: Vec<u8>,
+    format: MaskFormat,
+) -> Result<SegmentationResponse, (StatusCode, Json<ErrorResponse>)> {
+    WORKERS
+        .try_run(move || model.perform_segmentation(image_bytes, format))
+        .await
+        .and_then(|result| result)
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))
+}
+
+pub async fn run_embedding(
+    model: Arc<LoadedModel>,
+    image_bytes: Vec<u8>,
+) -> Result<Vec<f32>, (StatusCode, Json<ErrorResponse>)> {
+    WORKERS
+        .try_run(move || model.perform_embedding(image_bytes))
+        .await
+        .and_then(|result| result)
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))
+}
+
+pub async fn run_batch_classification(
+    model: Arc<LoadedModel>,
+    images: Vec<Result<Vec<u8>, ErrorResponse>>,
+    top_k: usize,
+) -> Result<Vec<BatchItemOutput>, (StatusCode, Json<ErrorResponse>)> {
+    WORKERS
+        .try_run(move || classify_batch_items(&model, images, top_k))
+        .await
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))?
+}
+
+fn classify_batch_items(
+    model: &LoadedModel,
+    images: Vec<Result<Vec<u8>, ErrorResponse>>,
+    top_k: usize,
+) -> Result<Vec<BatchItemOutput>, (StatusCode, Json<ErrorResponse>)> {
+    // Items that fail to decode keep their slot so results stay in request order
+    let mut tensors = Vec::with_capacity(images.len());
+    let mut results = Vec::with_capacity(images.len());
+    for image in images {
+        let tensor = image.and_then(|image_bytes| {
+            preprocess_image(image_bytes, &model.preprocess).map_err(|(err_code, err_msg)| {
+                let (_, Json(response)) = handle_error(err_code, err_msg);
+                response
+            })
+        });
+        match tensor {
+            Ok(tensor) => {
+                tensors.push(tensor);
+                results.push(None);
+            }
+            Err(err) => results.push(Some(Err(err))),
+        }
+    }
+
+    if !tensors.is_empty() {
+        let batch = Tensor::cat(&tensors, 0);
+        let mut predictions = model
+            .perform_batch_inference(batch, model.scores_needed(top_k))
+            .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))?
+            .into_iter();
+        for result in results.iter_mut().filter(|result| result.is_none()) {
+            *result = predictions
+                .next()
+                .map(|scores| Ok(model.to_prediction(scores, top_k)));
+        }
+    }
+
+    Ok(results
+        .into_iter()
+        .map(|result| {
+            result
+                .unwrap_or_else(|| Err(ErrorResponse::new(ErrorCode::OutputConversionFailed, None)))
+        })
+        .collect())
+}
+
+// A per-class threshold replaces min_confidence for its class, higher or lower; the margin
+// rule applies either way
+fn rejection_reason(
+    rejection: &RejectionConfig,
+    class_thresholds: &[Option<f32>],
+    scores: &[ClassScore],
+) -> Option<&'static str> {
+    let top = scores.first()?;
+    match class_thresholds.get(top.index as usize).copied().flatten() {
+        Some(threshold) if top.score < threshold => return Some("below_class_threshold"),
+        Some(_) => {}
+        None if top.score < rejection.min_confidence => return Some("below_min_confidence"),
+        None => {}
+    }
+    let runner_up = scores.get(1).map_or(0.0, |score| score.score);
+    if rejection.min_margin > 0.0 && top.score - runner_up < rejection.min_margin {
+        return Some("below_min_margin");
+    }
+    None
+}
 
-    let output =
-        perform_inference(tensor).map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))?;
-    Ok(output)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(values: &[(i64, f32)]) -> Vec<ClassScore> {
+        values
+            .iter()
+            .map(|&(index, score)| ClassScore {
+                index,
+                label: format!("class{}", index),
+                score,
+            })
+            .collect()
+    }
+
+    fn rejection(min_confidence: f32, min_margin: f32) -> RejectionConfig {
+        RejectionConfig {
+            min_confidence,
+            min_margin,
+            thresholds_path: None,
+        }
+    }
+
+    #[test]
+    fn rejects_below_the_global_minimum() {
+        let rejection = rejection(0.5, 0.0);
+        let reason = rejection_reason(&rejection, &[], &scores(&[(0, 0.4), (1, 0.3)]));
+        assert_eq!(reason, Some("below_min_confidence"));
+        assert_eq!(
+            rejection_reason(&rejection, &[], &scores(&[(0, 0.6)])),
+            None
+        );
+        assert_eq!(rejection_reason(&rejection, &[], &[]), None);
+    }
+
+    #[test]
+    fn prefers_class_thresholds_over_the_global_minimum() {
+        let rejection = rejection(0.5, 0.0);
+        let thresholds = [Some(0.3), Some(0.9), None];
+        // Lower than the global minimum for class 0, higher for class 1
+        assert_eq!(
+            rejection_reason(&rejection, &thresholds, &scores(&[(0, 0.4)])),
+            None
+        );
+        assert_eq!(
+            rejection_reason(&rejection, &thresholds, &scores(&[(1, 0.8)])),
+            Some("below_class_threshold")
+        );
+        assert_eq!(
+            rejection_reason(&rejection, &thresholds, &scores(&[(2, 0.4)])),
+            Some("below_min_confidence")
+        );
+    }
+
+    #[test]
+    fn rejects_a_narrow_margin() {
+        let rejection = rejection(0.0, 0.2);
+        assert_eq!(
+            rejection_reason(&rejection, &[], &scores(&[(0, 0.5), (1, 0.4)])),
+            Some("below_min_margin")
+        );
+        assert_eq!(
+            rejection_reason(&rejection, &[], &scores(&[(0, 0.5), (1, 0.2)])),
+            None
+        );
+        // The margin still applies when a class threshold passes
+        assert_eq!(
+            rejection_reason(&rejection, &[Some(0.1)], &scores(&[(0, 0.5), (1, 0.45)])),
+            Some("below_min_margin")
+        );
+    }
 }
@@ -0,0 +1,162 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+use tracing::{error, info};
+
+use crate::config::{AppConfig, CONFIG};
+use crate::errors::ErrorCode;
+use crate::model::LoadedModel;
+use crate::utils::common::latest_modified;
+
+lazy_static! {
+    pub static ref REGISTRY: ModelRegistry =
+        ModelRegistry::load(&CONFIG).expect("Failed to load models");
+}
+
+// Set once every model has loaded and passed its warm-up pass
+static READY: AtomicBool = AtomicBool::new(false);
+
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Acquire)
+}
+
+// Loads all models off the runtime at startup so the first request does not pay for it.
+// Exits on failure instead of serving /health forever while never becoming ready, so the
+// orchestrator restarts the process.
+pub async fn initialize() {
+    let result = tokio::task::spawn_blocking(|| {
+        for model in REGISTRY.list() {
+            model
+                .warm_up()
+                .map_err(|err| format!("{}: {}", model.name, err))?;
+        }
+        Ok::<_, String>(())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {
+            READY.store(true, Ordering::Release);
+            info!("Models loaded and warmed up");
+            spawn_watcher(CONFIG.reload_poll_secs);
+        }
+        Ok(Err(err)) => {
+            error!("Model warm-up failed: {}", err);
+            process::exit(1);
+        }
+        Err(err) => {
+            error!("Model loading failed: {:?}", err);
+            process::exit(1);
+        }
+    }
+}
+
+pub struct ModelRegistry {
+    models: RwLock<Vec<Arc<LoadedModel>>>, // Kept in config order for listing
+    default_model: String,
+    reload_lock: Mutex<()>,
+}
+
+impl ModelRegistry {
+    fn load(config: &AppConfig) -> Result<ModelRegistry, String> {
+        let models = config
+            .model_configs()
+            .iter()
+            .map(LoadedModel::load)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ModelRegistry {
+            models: RwLock::new(models),
+            default_model: config.default_model_name(),
+            reload_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<LoadedModel>> {
+        self.models
+            .read()
+            .unwrap()
+            .iter()
+            .find(|model| model.name == name)
+            .cloned()
+    }
+
+    // The first configured model matching a capability, used by routes without a model name
+    pub fn first(&self, predicate: impl Fn(&LoadedModel) -> bool) -> Option<Arc<LoadedModel>> {
+        self.models
+            .read()
+            .unwrap()
+            .iter()
+            .find(|model| predicate(model))
+            .cloned()
+    }
+
+    pub fn default_model_name(&self) -> &str {
+        &self.default_model
+    }
+
+    pub fn list(&self) -> Vec<Arc<LoadedModel>> {
+        self.models.read().unwrap().clone()
+    }
+
+    // Loads and warms up a fresh copy off the runtime, then swaps it in; requests
+    // still holding the previous model finish on it
+    pub async fn reload(&self, name: &str) -> Result<Arc<LoadedModel>, (ErrorCode, String)> {
+        let _guard = self.reload_lock.lock().await;
+        let config = self
+            .get(name)
+            .ok_or((ErrorCode::ModelNotFound, name.to_string()))?
+            .model_config();
+
+        let model = tokio::task::spawn_blocking(move || {
+            let model = LoadedModel::load(&config)?;
+            model.warm_up()?;
+            Ok::<_, String>(model)
+        })
+        .await
+        .map_err(|err| (ErrorCode::ModelReloadFailed, err.to_string()))?
+        .map_err(|err| (ErrorCode::ModelReloadFailed, err))?;
+
+        let mut models = self.models.write().unwrap();
+        if let Some(slot) = models.iter_mut().find(|model| model.name == name) {
+            *slot = model.clone();
+        }
+        info!("Reloaded model {}", name);
+        Ok(model)
+    }
+}
+
+// Polls model and class files and reloads a model once either file changes
+fn spawn_watcher(poll_secs: u64) {
+    if poll_secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(poll_secs));
+        let mut attempted: HashMap<String, SystemTime> = HashMap::new();
+        loop {
+            ticker.tick().await;
+            for model in REGISTRY.list() {
+                let Some(modified) = latest_modified(&model.watched_paths()) else {
+                    continue;
+                };
+                // Skip files already loaded, and files whose last reload attempt failed
+                if Some(modified) <= model.modified || attempted.get(&model.name) == Some(&modified)
+                {
+                    continue;
+                }
+                attempted.insert(model.name.clone(), modified);
+
+                info!("Detected changes for model {}, reloading", model.name);
+                if let Err((_, err)) = REGISTRY.reload(&model.name).await {
+                    error!("Failed to reload model {}: {}", model.name, err);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,87 @@
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info_span, Instrument};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Client-supplied ids longer than this, or with non-printable characters, are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    static TASK_REQUEST_ID: String;
+}
+
+thread_local! {
+    // Set while a worker thread runs a job on behalf of a request
+    static THREAD_REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Takes the id from X-Request-Id or generates one, runs the request inside a span carrying
+// it and echoes it back in the response header
+pub async fn assign_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(generate);
+
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let mut response = TASK_REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// The id of the request being handled, on the async task or on a worker thread
+pub fn current() -> Option<String> {
+    TASK_REQUEST_ID
+        .try_with(Clone::clone)
+        .ok()
+        .or_else(|| THREAD_REQUEST_ID.with(|id| id.borrow().clone()))
+}
+
+// Makes a request id visible to current() while running work on another thread
+pub fn with_request_id<T>(request_id: Option<String>, f: impl FnOnce() -> T) -> T {
+    let previous = THREAD_REQUEST_ID.with(|id| id.replace(request_id));
+    let result = f();
+    THREAD_REQUEST_ID.with(|id| *id.borrow_mut() = previous);
+    result
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn generate() -> String {
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default();
+    // RandomState is seeded per process, so ids differ across restarts
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(nanos);
+    hasher.write_u64(count);
+    format!("{:016x}{:08x}", hasher.finish(), count as u32)
+}
@@ -1,38 +1,683 @@
-use crate::errors::ErrorResponse;
-use crate::model::run_classification;
-use crate::types::{ImageInput, ImagePrediction};
-use axum::{http::StatusCode, Json};
+use crate::config::{ModelTask, CONFIG};
+use crate::errors::{handle_error, ErrorCode, ErrorResponse};
+use crate::index::INDEX;
+use crate::model::{
+    run_batch_classification, run_classification, run_detection, run_embedding, run_segmentation,
+    run_tta_classification, LoadedModel,
+};
+use crate::registry::{is_ready, ModelRegistry, REGISTRY};
+use crate::types::{
+    BatchImageInput, BatchItemResult, BatchPrediction, ClassifyParams, DetectionResponse,
+    EmbedParams, EmbeddingData, EmbeddingEncoding, EmbeddingResponse, ImageInput, ImagePrediction,
+    IndexInfo, IndexInsertInput, IndexInsertResponse, IndexQueryInput, IndexQueryResponse,
+    ModelInfo, ModelList, SegmentParams, SegmentationResponse,
+};
+use crate::utils::common::log_elapsed_time;
+use crate::utils::fetch::fetch_image;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Multipart, Path, Query, Request};
+use axum::http::header::{CONTENT_TYPE, RETRY_AFTER};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, Method, StatusCode, Uri};
+use axum::response::Response;
+use axum::Json;
 use base64::prelude::*;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
 use std::time::Instant;
-use crate::utils::common::log_elapsed_time;
 
-pub async fn classify(
-    Json(payload): Json<ImageInput>,
-) -> Result<(StatusCode, Json<ImagePrediction>), (StatusCode, Json<ErrorResponse>)> {
-    let start_time = Instant::now();
-    let image_bytes = BASE64_STANDARD.decode(payload.image).map_err(|_| {
+fn decode_image(image: &str) -> Result<Vec<u8>, (ErrorCode, String)> {
+    let image_bytes = BASE64_STANDARD.decode(image).map_err(|_| {
         (
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "Invalid Base64 input".to_string(),
-            }),
+            ErrorCode::InvalidInputData,
+            "Invalid Base64 input".to_string(),
         )
     })?;
 
     if image_bytes.is_empty() {
-        return Err((
+        return Err((ErrorCode::InvalidRequest, "No image uploaded".to_string()));
+    }
+    Ok(image_bytes)
+}
+
+// Model routes answer 503 until startup loading and warm-up have finished
+fn registry() -> Result<&'static ModelRegistry, (StatusCode, Json<ErrorResponse>)> {
+    if !is_ready() {
+        return Err(handle_error(ErrorCode::ModelNotReady, "Registry not ready"));
+    }
+    Ok(&REGISTRY)
+}
+
+fn find_model(name: Option<&str>) -> Result<Arc<LoadedModel>, (StatusCode, Json<ErrorResponse>)> {
+    let registry = registry()?;
+    let name = name.unwrap_or(registry.default_model_name());
+    registry
+        .get(name)
+        .ok_or_else(|| handle_error(ErrorCode::ModelNotFound, name))
+}
+
+fn require_task(
+    model: &LoadedModel,
+    task: ModelTask,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if model.task != task {
+        return Err(handle_error(
+            ErrorCode::UnsupportedTask,
+            format!("Model {} is a {:?} model", model.name, model.task),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn health_check() -> Result<(StatusCode, String), (StatusCode, Json<ErrorResponse>)> {
+    Ok((StatusCode::OK, "Server is working!".to_string()))
+}
+
+pub async fn ready_check() -> Result<(StatusCode, String), (StatusCode, Json<ErrorResponse>)> {
+    registry()?;
+    Ok((StatusCode::OK, "Models are ready!".to_string()))
+}
+
+pub async fn info() -> Result<(StatusCode, Json<ModelInfo>), (StatusCode, Json<ErrorResponse>)> {
+    let model = find_model(None)?;
+    Ok((StatusCode::OK, Json(model_info(&model))))
+}
+
+pub async fn list_models(
+) -> Result<(StatusCode, Json<ModelList>), (StatusCode, Json<ErrorResponse>)> {
+    let models = registry()?
+        .list()
+        .iter()
+        .map(|model| model_info(model))
+        .collect();
+    Ok((StatusCode::OK, Json(ModelList { models })))
+}
+
+pub async fn reload_model(
+    ApiPath(name): ApiPath<String>,
+) -> Result<(StatusCode, Json<ModelInfo>), (StatusCode, Json<ErrorResponse>)> {
+    let start_time = Instant::now();
+    let model = registry()?
+        .reload(&name)
+        .await
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))?;
+
+    log_elapsed_time("Model reload", start_time);
+    Ok((StatusCode::OK, Json(model_info(&model))))
+}
+
+// Tells clients when to come back after a 503 (busy workers or models still loading)
+pub async fn add_retry_after(mut response: Response) -> Response {
+    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(CONFIG.retry_after_secs));
+    }
+    response
+}
+
+fn model_info(model: &LoadedModel) -> ModelInfo {
+    ModelInfo {
+        name: model.name.clone(),
+        model_path: model.model_path.clone(),
+        class_path: model.class_path.clone(),
+        backend: model.backend_kind,
+        task: model.task,
+        embedding: model.supports_embedding(),
+        device: format!("{:?}", model.device()),
+        num_classes: model.classes.len(),
+        input_shape: [3, model.preprocess.crop_size, model.preprocess.crop_size],
+        default: model.name == REGISTRY.default_model_name(),
+    }
+}
+
+pub async fn classify(
+    request: Request,
+) -> Result<(StatusCode, Json<ImagePrediction>), (StatusCode, Json<ErrorResponse>)> {
+    classify_with(find_model(None)?, request).await
+}
+
+pub async fn classify_model(
+    ApiPath(name): ApiPath<String>,
+    request: Request,
+) -> Result<(StatusCode, Json<ImagePrediction>), (StatusCode, Json<ErrorResponse>)> {
+    classify_with(find_model(Some(&name))?, request).await
+}
+
+async fn classify_with(
+    model: Arc<LoadedModel>,
+    request: Request,
+) -> Result<(StatusCode, Json<ImagePrediction>), (StatusCode, Json<ErrorResponse>)> {
+    let start_time = Instant::now();
+    require_task(&model, ModelTask::Classification)?;
+    let (image_bytes, params) = extract_image(request).await?;
+
+    let top_k = params.top_k.unwrap_or(CONFIG.default_top_k);
+    let prediction = if params.tta.unwrap_or(false) {
+        run_tta_classification(model, image_bytes, top_k).await?
+    } else {
+        run_classification(&model, image_bytes, top_k).await?
+    };
+
+    log_elapsed_time("Inference", start_time);
+    Ok((StatusCode::OK, Json(prediction)))
+}
+
+pub async fn detect(
+    request: Request,
+) -> Result<(StatusCode, Json<DetectionResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let model = registry()?
+        .first(|model| model.task == ModelTask::Detection)
+        .ok_or_else(|| handle_error(ErrorCode::ModelNotFound, "No detection model configured"))?;
+    detect_with(model, request).await
+}
+
+pub async fn detect_model(
+    ApiPath(name): ApiPath<String>,
+    request: Request,
+) -> Result<(StatusCode, Json<DetectionResponse>), (StatusCode, Json<ErrorResponse>)> {
+    detect_with(find_model(Some(&name))?, request).await
+}
+
+async fn detect_with(
+    model: Arc<LoadedModel>,
+    request: Request,
+) -> Result<(StatusCode, Json<DetectionResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let start_time = Instant::now();
+    require_task(&model, ModelTask::Detection)?;
+    let (image_bytes, _) = extract_image(request).await?;
+
+    let detections = run_detection(model, image_bytes).await?;
+    log_elapsed_time("Detection", start_time);
+    Ok((StatusCode::OK, Json(detections)))
+}
+
+pub async fn segment(
+    ApiQuery(params): ApiQuery<SegmentParams>,
+    request: Request,
+) -> Result<(StatusCode, Json<SegmentationResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let model = registry()?
+        .first(|model| model.task == ModelTask::Segmentation)
+        .ok_or_else(|| {
+            handle_error(ErrorCode::ModelNotFound, "No segmentation model configured")
+        })?;
+    segment_with(model, params, request).await
+}
+
+pub async fn segment_model(
+    ApiPath(name): ApiPath<String>,
+    ApiQuery(params): ApiQuery<SegmentParams>,
+    request: Request,
+) -> Result<(StatusCode, Json<SegmentationResponse>), (StatusCode, Json<ErrorResponse>)> {
+    segment_with(find_model(Some(&name))?, params, request).await
+}
+
+async fn segment_with(
+    model: Arc<LoadedModel>,
+    params: SegmentParams,
+    request: Request,
+) -> Result<(StatusCode, Json<SegmentationResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let start_time = Instant::now();
+    require_task(&model, ModelTask::Segmentation)?;
+    let (image_bytes, _) = extract_image(request).await?;
+
+    let format = params.format.unwrap_or_default();
+    let segmentation = run_segmentation(model, image_bytes, format).await?;
+
+    log_elapsed_time("Segmentation", start_time);
+    Ok((StatusCode::OK, Json(segmentation)))
+}
+
+pub async fn embed(
+    ApiQuery(params): ApiQuery<EmbedParams>,
+    request: Request,
+) -> Result<(StatusCode, Json<EmbeddingResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let model = registry()?
+        .first(|model| model.supports_embedding())
+        .ok_or_else(|| handle_error(ErrorCode::ModelNotFound, "No embedding model configured"))?;
+    embed_with(model, params, request).await
+}
+
+pub async fn embed_model(
+    ApiPath(name): ApiPath<String>,
+    ApiQuery(params): ApiQuery<EmbedParams>,
+    request: Request,
+) -> Result<(StatusCode, Json<EmbeddingResponse>), (StatusCode, Json<ErrorResponse>)> {
+    embed_with(find_model(Some(&name))?, params, request).await
+}
+
+async fn embed_with(
+    model: Arc<LoadedModel>,
+    params: EmbedParams,
+    request: Request,
+) -> Result<(StatusCode, Json<EmbeddingResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let start_time = Instant::now();
+    if !model.supports_embedding() {
+        return Err(handle_error(
+            ErrorCode::UnsupportedTask,
+            format!("Model {} has no embedding output", model.name),
+        ));
+    }
+    let (image_bytes, _) = extract_image(request).await?;
+
+    let name = model.name.clone();
+    let embedding = run_embedding(model, image_bytes).await?;
+    let encoding = params.encoding.unwrap_or_default();
+    let dimension = embedding.len();
+    let embedding = match encoding {
+        EmbeddingEncoding::Float => EmbeddingData::Float(embedding),
+        EmbeddingEncoding::Base64 => {
+            let bytes: Vec<u8> = embedding
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            EmbeddingData::Base64(BASE64_STANDARD.encode(bytes))
+        }
+    };
+
+    log_elapsed_time("Embedding", start_time);
+    Ok((
+        StatusCode::OK,
+        Json(EmbeddingResponse {
+            model: name,
+            dimension,
+            encoding,
+            embedding,
+        }),
+    ))
+}
+
+// Picks the upload flavour from Content-Type: multipart form, raw image body or base64 JSON
+async fn extract_image(
+    request: Request,
+) -> Result<(Vec<u8>, ClassifyParams), (StatusCode, Json<ErrorResponse>)> {
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    if content_type.starts_with("multipart/form-data") {
+        let multipart = Multipart::from_request(request, &())
+            .await
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text()))?;
+        read_multipart(multipart).await
+    } else if content_type.starts_with("image/")
+        || content_type.starts_with("application/octet-stream")
+    {
+        let Query(params) = Query::<ClassifyParams>::try_from_uri(request.uri())
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text()))?;
+        let body = Bytes::from_request(request, &())
+            .await
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text()))?;
+        if body.is_empty() {
+            return Err(error_response(StatusCode::BAD_REQUEST, "No image uploaded"));
+        }
+        Ok((body.to_vec(), params))
+    } else {
+        let Json(payload) = Json::<ImageInput>::from_request(request, &())
+            .await
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text()))?;
+        let image_bytes = load_image(payload.image, payload.image_url).await?;
+        let params = ClassifyParams {
+            top_k: payload.top_k,
+            tta: payload.tta,
+        };
+        Ok((image_bytes, params))
+    }
+}
+
+async fn load_image(
+    image: Option<String>,
+    image_url: Option<String>,
+) -> Result<Vec<u8>, (StatusCode, Json<ErrorResponse>)> {
+    match (image, image_url) {
+        (Some(image), None) => {
+            decode_image(&image).map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))
+        }
+        (None, Some(image_url)) => fetch_image(&image_url)
+            .await
+            .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg)),
+        _ => Err(error_response(
             StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "No image uploaded".to_string(),
-            }),
+            "Provide exactly one of image or image_url",
+        )),
+    }
+}
+
+async fn read_multipart(
+    mut multipart: Multipart,
+) -> Result<(Vec<u8>, ClassifyParams), (StatusCode, Json<ErrorResponse>)> {
+    let mut image_bytes = None;
+    let mut params = ClassifyParams::default();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| error_response(err.status(), err.body_text()))?
+    {
+        match field.name() {
+            Some("image") | Some("file") => {
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|err| error_response(err.status(), err.body_text()))?;
+                image_bytes = Some(bytes.to_vec());
+            }
+            Some("top_k") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|err| error_response(err.status(), err.body_text()))?;
+                let value = text
+                    .trim()
+                    .parse()
+                    .map_err(|_| error_response(StatusCode::BAD_REQUEST, "Invalid top_k value"))?;
+                params.top_k = Some(value);
+            }
+            Some("tta") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|err| error_response(err.status(), err.body_text()))?;
+                let value = text
+                    .trim()
+                    .parse()
+                    .map_err(|_| error_response(StatusCode::BAD_REQUEST, "Invalid tta value"))?;
+                params.tta = Some(value);
+            }
+            _ => {}
+        }
+    }
+
+    match image_bytes {
+        Some(image_bytes) if !image_bytes.is_empty() => Ok((image_bytes, params)),
+        _ => Err(error_response(StatusCode::BAD_REQUEST, "No image uploaded")),
+    }
+}
+
+// Json extractor whose rejections use the structured error body
+pub struct ApiJson<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for ApiJson<T> {
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request, state)
+            .await
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text()))?;
+        Ok(ApiJson(value))
+    }
+}
+
+// Query extractor whose rejections use the structured error body
+pub struct ApiQuery<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for ApiQuery<T> {
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text()))?;
+        Ok(ApiQuery(value))
+    }
+}
+
+// Path extractor whose rejections use the structured error body
+pub struct ApiPath<T>(pub T);
+
+impl<T: DeserializeOwned + Send, S: Send + Sync> FromRequestParts<S> for ApiPath<T> {
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text()))?;
+        Ok(ApiPath(value))
+    }
+}
+
+// Router fallbacks, so unmatched paths and methods get the structured error body as well
+pub async fn route_not_found(method: Method, uri: Uri) -> (StatusCode, Json<ErrorResponse>) {
+    handle_error(
+        ErrorCode::RouteNotFound,
+        format!("No route for {} {}", method, uri.path()),
+    )
+}
+
+// axum still adds the Allow header listing the route's methods
+pub async fn method_not_allowed(method: Method, uri: Uri) -> (StatusCode, Json<ErrorResponse>) {
+    handle_error(
+        ErrorCode::MethodNotAllowed,
+        format!("{} is not allowed on {}", method, uri.path()),
+    )
+}
+
+fn error_response(
+    status: StatusCode,
+    error: impl Into<String>,
+) -> (StatusCode, Json<ErrorResponse>) {
+    handle_error(ErrorCode::from_status(status), error.into())
+}
+
+pub async fn classify_batch(
+    ApiJson(payload): ApiJson<BatchImageInput>,
+) -> Result<(StatusCode, Json<BatchPrediction>), (StatusCode, Json<ErrorResponse>)> {
+    classify_batch_with(find_model(None)?, payload).await
+}
+
+pub async fn classify_model_batch(
+    ApiPath(name): ApiPath<String>,
+    ApiJson(payload): ApiJson<BatchImageInput>,
+) -> Result<(StatusCode, Json<BatchPrediction>), (StatusCode, Json<ErrorResponse>)> {
+    classify_batch_with(find_model(Some(&name))?, payload).await
+}
+
+async fn classify_batch_with(
+    model: Arc<LoadedModel>,
+    payload: BatchImageInput,
+) -> Result<(StatusCode, Json<BatchPrediction>), (StatusCode, Json<ErrorResponse>)> {
+    let start_time = Instant::now();
+    require_task(&model, ModelTask::Classification)?;
+    if payload.images.is_empty() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "No image uploaded"));
+    }
+    if payload.images.len() > CONFIG.max_batch_images {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Batch exceeds {} images", CONFIG.max_batch_images),
         ));
     }
 
-    match run_classification(image_bytes) {
-        Ok(label) => {
-            log_elapsed_time("Inference", start_time);
-            Ok((StatusCode::OK, Json(ImagePrediction { label })))
+    let images = payload
+        .images
+        .iter()
+        .map(|image| {
+            decode_image(image).map_err(|(err_code, err_msg)| {
+                let (_, Json(response)) = handle_error(err_code, err_msg);
+                response
+            })
+        })
+        .collect();
+    let top_k = payload.top_k.unwrap_or(CONFIG.default_top_k);
+    let results = run_batch_classification(model, images, top_k)
+        .await?
+        .into_iter()
+        .map(|result| match result {
+            Ok(prediction) => BatchItemResult::Prediction(prediction),
+            Err(err) => BatchItemResult::Error(err),
+        })
+        .collect();
+
+    log_elapsed_time("Batch inference", start_time);
+    Ok((StatusCode::OK, Json(BatchPrediction { results })))
+}
+
+const DEFAULT_NEIGHBOURS: usize = 10;
+
+pub async fn index_info() -> Result<(StatusCode, Json<IndexInfo>), (StatusCode, Json<ErrorResponse>)>
+{
+    Ok((
+        StatusCode::OK,
+        Json(IndexInfo {
+            entries: INDEX.len(),
+            dimension: INDEX.dimension(),
+        }),
+    ))
+}
+
+pub async fn index_insert(
+    ApiJson(payload): ApiJson<IndexInsertInput>,
+) -> Result<(StatusCode, Json<IndexInsertResponse>), (StatusCode, Json<ErrorResponse>)> {
+    if payload.id.is_empty() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "id must be non-empty",
+        ));
+    }
+    let vector = match payload.vector {
+        Some(vector) if payload.image.is_none() && payload.image_url.is_none() => vector,
+        Some(_) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                "Provide exactly one of image, image_url or vector",
+            ))
         }
-        Err(err) => Err(err),
+        None => embed_for_index(payload.image, payload.image_url).await?,
+    };
+
+    let id = payload.id;
+    let dimension = vector.len();
+    let replaced = run_blocking(ErrorCode::IndexPersistFailed, {
+        let id = id.clone();
+        move || INDEX.insert(id, vector)
+    })
+    .await?;
+
+    let status = if replaced {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((
+        status,
+        Json(IndexInsertResponse {
+            id,
+            dimension,
+            replaced,
+        }),
+    ))
+}
+
+pub async fn index_delete(
+    ApiPath(id): ApiPath<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    run_blocking(ErrorCode::IndexPersistFailed, move || INDEX.remove(&id)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn index_query(
+    ApiJson(payload): ApiJson<IndexQueryInput>,
+) -> Result<(StatusCode, Json<IndexQueryResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let start_time = Instant::now();
+    let k = payload.k.unwrap_or(DEFAULT_NEIGHBOURS);
+    let sources = [
+        payload.id.is_some(),
+        payload.vector.is_some(),
+        payload.image.is_some() || payload.image_url.is_some(),
+    ];
+    if sources.iter().filter(|source| **source).count() != 1 {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Provide exactly one of id, image, image_url or vector",
+        ));
+    }
+
+    let vector = match (&payload.id, payload.vector) {
+        (Some(id), _) => INDEX
+            .get(id)
+            .ok_or_else(|| handle_error(ErrorCode::IndexEntryNotFound, id))?,
+        (None, Some(vector)) => vector,
+        (None, None) => embed_for_index(payload.image, payload.image_url).await?,
+    };
+    // Querying by id should not return the entry itself
+    let exclude = payload.id;
+    let matches = run_blocking(ErrorCode::IndexQueryFailed, move || {
+        INDEX.query(vector, k, exclude.as_deref())
+    })
+    .await?;
+
+    log_elapsed_time("Index query", start_time);
+    Ok((StatusCode::OK, Json(IndexQueryResponse { matches })))
+}
+
+async fn embed_for_index(
+    image: Option<String>,
+    image_url: Option<String>,
+) -> Result<Vec<f32>, (StatusCode, Json<ErrorResponse>)> {
+    let model = match &CONFIG.index_model {
+        Some(name) => find_model(Some(name))?,
+        None => registry()?
+            .first(|model| model.supports_embedding())
+            .ok_or_else(|| {
+                handle_error(ErrorCode::ModelNotFound, "No embedding model configured")
+            })?,
+    };
+    let image_bytes = load_image(image, image_url).await?;
+    run_embedding(model, image_bytes).await
+}
+
+// Index changes rewrite the index file and queries scan every entry, so both run off the
+// async runtime; a panic in f is reported with the given code
+async fn run_blocking<T, F>(
+    error_code: ErrorCode,
+    f: F,
+) -> Result<T, (StatusCode, Json<ErrorResponse>)>
+where
+    F: FnOnce() -> Result<T, (ErrorCode, String)> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| handle_error(error_code, err))?
+        .map_err(|(err_code, err_msg)| handle_error(err_code, err_msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header::ALLOW;
+    use axum::routing::get;
+    use axum::Router;
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn answers_unmatched_routes_and_methods_with_error_bodies() {
+        let app = Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .fallback(route_not_found)
+            .method_not_allowed_fallback(method_not_allowed);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = reqwest::Client::new();
+
+        let response = client.get(format!("{}/nope", base)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(body["code"], "route_not_found");
+        assert_eq!(body["details"], "No route for GET /nope");
+
+        let response = client
+            .post(format!("{}/health", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW.as_str()], "GET,HEAD");
+        let body: Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(body["code"], "method_not_allowed");
     }
 }
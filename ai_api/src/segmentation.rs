@@ -0,0 +1,315 @@
+use tch::{Device, Kind, Tensor};
+
+use crate::utils::image::ImageTransform;
+
+// Reported for pixels that map outside the model input, which exact and letterbox resizing avoid
+pub const BACKGROUND_CLASS: u8 = 0;
+
+// 8-neighbourhood in clockwise order, starting west
+const NEIGHBOURS: [(i64, i64); 8] = [
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+];
+
+// Pixel-edge steps in clockwise order: east, south, west, north
+const EDGE_DIRECTIONS: [(i64, i64); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+// A class index per pixel of the original image, row-major
+pub struct ClassMask {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+// Checks a raw output is [N, classes, H, W] logits
+pub fn validate_output(output: &Tensor, num_classes: usize) -> Result<(), String> {
+    let size = output.size();
+    match size.as_slice() {
+        [_, classes, _, _] if *classes as usize == num_classes && num_classes <= 256 => Ok(()),
+        _ => Err(format!(
+            "Segmentation output shape {:?} does not match {} classes (at most 256)",
+            size, num_classes
+        )),
+    }
+}
+
+// Takes the per-pixel argmax at model input size, then maps it back onto the original image
+pub fn class_mask(
+    output: &Tensor,
+    num_classes: usize,
+    input_size: u32,
+    transform: ImageTransform,
+) -> Result<ClassMask, String> {
+    validate_output(output, num_classes)?;
+    let size = input_size as i64;
+    let mut logits = output.get(0).unsqueeze(0).to_kind(Kind::Float);
+    if logits.size()[2..] != [size, size] {
+        logits = logits.upsample_bilinear2d([size, size], false, None, None);
+    }
+    let classes = logits.argmax(1, false).get(0).to_kind(Kind::Uint8);
+    let classes = Vec::<u8>::try_from(&classes.flatten(0, -1).to_device(Device::Cpu))
+        .map_err(|err| err.to_string())?;
+
+    let (width, height) = (transform.width, transform.height);
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let (model_x, model_y) = transform.to_model(x as f32 + 0.5, y as f32 + 0.5);
+            let (model_x, model_y) = (model_x.floor() as i64, model_y.floor() as i64);
+            let class = if (0..size).contains(&model_x) && (0..size).contains(&model_y) {
+                classes[(model_y * size + model_x) as usize]
+            } else {
+                BACKGROUND_CLASS
+            };
+            pixels.push(class);
+        }
+    }
+
+    Ok(ClassMask {
+        width,
+        height,
+        pixels,
+    })
+}
+
+impl ClassMask {
+    // Pixel count per class index, for every class present
+    pub fn class_counts(&self) -> Vec<(u8, u64)> {
+        let mut counts = [0u64; 256];
+        for &class in &self.pixels {
+            counts[class as usize] += 1;
+        }
+        (0..=255u8)
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    // 8-bit palette PNG whose pixel values are class indices
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(palette());
+        let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(|err| err.to_string())?;
+        writer.finish().map_err(|err| err.to_string())?;
+
+        Ok(bytes)
+    }
+
+    // COCO-style uncompressed RLE: column-major run lengths, starting with a run of zeros
+    pub fn to_rle(&self, class: u8) -> Vec<u32> {
+        let mut counts = Vec::new();
+        let mut current = false;
+        let mut run = 0u32;
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let inside = self.get(x as i64, y as i64) == Some(class);
+                if inside != current {
+                    counts.push(run);
+                    current = inside;
+                    run = 0;
+                }
+                run += 1;
+            }
+        }
+        counts.push(run);
+        counts
+    }
+
+    // Outer boundary of every 8-connected region of the class along pixel edges; holes are not traced
+    pub fn to_polygons(&self, class: u8) -> Vec<Vec<[u32; 2]>> {
+        let mut visited = vec![false; self.pixels.len()];
+        let mut polygons = Vec::new();
+        for y in 0..self.height as i64 {
+            for x in 0..self.width as i64 {
+                let index = self.index(x, y);
+                if visited[index] || self.pixels[index] != class {
+                    continue;
+                }
+                // Raster order guarantees the pixels above and to the west are outside the region
+                polygons.push(self.trace_contour(class, (x, y)));
+                self.fill_region(class, (x, y), &mut visited);
+            }
+        }
+        polygons
+    }
+
+    fn get(&self, x: i64, y: i64) -> Option<u8> {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return None;
+        }
+        Some(self.pixels[self.index(x, y)])
+    }
+
+    fn index(&self, x: i64, y: i64) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    // Follows the pixel edges around the region clockwise, with the region on the right, and
+    // keeps the corners; vertices are pixel corners, so a single pixel is a unit square
+    fn trace_contour(&self, class: u8, start: (i64, i64)) -> Vec<[u32; 2]> {
+        let inside = |x: i64, y: i64| self.get(x, y) == Some(class);
+        // Starts along the top edge of the start pixel
+        let (mut x, mut y) = start;
+        let mut dir = 0;
+        let mut points = Vec::new();
+        let max_steps = 4 * self.pixels.len();
+        for _ in 0..max_steps {
+            let (dx, dy) = EDGE_DIRECTIONS[dir];
+            (x, y) = (x + dx, y + dy);
+            // The two pixels ahead of the vertex, left and right of the direction of travel
+            let (left, right) = match dir {
+                0 => (inside(x, y - 1), inside(x, y)),
+                1 => (inside(x, y), inside(x - 1, y)),
+                2 => (inside(x - 1, y), inside(x - 1, y - 1)),
+                _ => (inside(x - 1, y - 1), inside(x, y - 1)),
+            };
+            // A region pixel ahead on the left is diagonally connected, so the edge turns left
+            let next = match (left, right) {
+                (true, _) => (dir + 3) % 4,
+                (false, true) => dir,
+                (false, false) => (dir + 1) % 4,
+            };
+            if next != dir {
+                points.push([x as u32, y as u32]);
+            }
+            dir = next;
+            // Done on re-entering the start edge in the start direction, not on the first visit
+            // of the start vertex, which a pinch point passes through twice
+            if (x, y) == start && dir == 0 {
+                break;
+            }
+        }
+        points
+    }
+
+    fn fill_region(&self, class: u8, start: (i64, i64), visited: &mut [bool]) {
+        let mut stack = vec![start];
+        visited[self.index(start.0, start.1)] = true;
+        while let Some((x, y)) = stack.pop() {
+            for (dx, dy) in NEIGHBOURS {
+                let (nx, ny) = (x + dx, y + dy);
+                if self.get(nx, ny) == Some(class) && !visited[self.index(nx, ny)] {
+                    visited[self.index(nx, ny)] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+}
+
+// PASCAL VOC colour map, so masks look familiar in image viewers
+fn palette() -> Vec<u8> {
+    let mut palette = Vec::with_capacity(256 * 3);
+    for index in 0..256u32 {
+        let (mut r, mut g, mut b) = (0u8, 0u8, 0u8);
+        let mut bits = index;
+        for shift in (0..8).rev() {
+            r |= ((bits & 1) as u8) << shift;
+            g |= (((bits >> 1) & 1) as u8) << shift;
+            b |= (((bits >> 2) & 1) as u8) << shift;
+            bits >>= 3;
+        }
+        palette.extend([r, g, b]);
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a mask from rows of '0'/'1', one string per row
+    fn mask(rows: &[&str]) -> ClassMask {
+        ClassMask {
+            width: rows[0].len() as u32,
+            height: rows.len() as u32,
+            pixels: rows
+                .iter()
+                .flat_map(|row| row.bytes().map(|byte| byte - b'0'))
+                .collect(),
+        }
+    }
+
+    // Shoelace area, which is the pixel count for a hole-free region
+    fn area(polygon: &[[u32; 2]]) -> i64 {
+        let count = polygon.len();
+        let twice: i64 = (0..count)
+            .map(|i| {
+                let ([x0, y0], [x1, y1]) = (polygon[i], polygon[(i + 1) % count]);
+                x0 as i64 * y1 as i64 - x1 as i64 * y0 as i64
+            })
+            .sum();
+        twice.abs() / 2
+    }
+
+    #[test]
+    fn encodes_rle_column_major() {
+        let mask = mask(&["0110", "0100"]);
+        // Columns top to bottom: 00 | 11 | 10 | 00
+        assert_eq!(mask.to_rle(1), vec![2, 3, 3]);
+        assert_eq!(mask.to_rle(0), vec![0, 2, 3, 3]);
+        assert_eq!(mask.to_rle(7), vec![8]);
+        assert_eq!(mask.class_counts(), vec![(0, 5), (1, 3)]);
+    }
+
+    #[test]
+    fn traces_filled_block_along_pixel_edges() {
+        let mask = mask(&["0000", "0110", "0110", "0000"]);
+        assert_eq!(
+            mask.to_polygons(1),
+            vec![vec![[3, 1], [3, 3], [1, 3], [1, 1]]]
+        );
+        assert_eq!(area(&mask.to_polygons(1)[0]), 4);
+    }
+
+    #[test]
+    fn keeps_every_lobe_around_a_cut_vertex() {
+        let polygons = mask(&["010", "101"]).to_polygons(1);
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(area(&polygons[0]), 3);
+        for corner in [[1, 0], [0, 1], [0, 2], [3, 2]] {
+            assert!(polygons[0].contains(&corner), "{:?}", corner);
+        }
+
+        let polygons = mask(&["00100", "01010", "10001"]).to_polygons(1);
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(area(&polygons[0]), 5);
+        assert!(polygons[0].contains(&[0, 3]) && polygons[0].contains(&[5, 3]));
+    }
+
+    #[test]
+    fn traces_single_pixels_and_lines_as_rectangles() {
+        assert_eq!(
+            mask(&["000", "010", "000"]).to_polygons(1),
+            vec![vec![[2, 1], [2, 2], [1, 2], [1, 1]]]
+        );
+        assert_eq!(
+            mask(&["0000", "1110"]).to_polygons(1),
+            vec![vec![[3, 1], [3, 2], [0, 2], [0, 1]]]
+        );
+        assert_eq!(
+            mask(&["1", "1", "1"]).to_polygons(1),
+            vec![vec![[1, 0], [1, 3], [0, 3], [0, 0]]]
+        );
+    }
+
+    #[test]
+    fn traces_separate_regions_and_ignores_holes() {
+        let polygons = mask(&["11100", "10100", "11101"]).to_polygons(1);
+        assert_eq!(polygons.len(), 2);
+        assert_eq!(area(&polygons[0]), 9);
+        assert_eq!(polygons[1], vec![[5, 2], [5, 3], [4, 3], [4, 2]]);
+    }
+}
@@ -1,11 +1,248 @@
 use serde::{Deserialize, Serialize};
 
+use crate::config::{BackendKind, ModelTask};
+use crate::errors::ErrorResponse;
+
 #[derive(Debug, Deserialize)]
 pub struct ImageInput {
-    pub image: String, // Base64-encoded image string
+    pub image: Option<String>,     // Base64-encoded image string
+    pub image_url: Option<String>, // Alternatively, an image the server downloads itself
+    pub top_k: Option<usize>,      // Number of best classes to return
+    pub tta: Option<bool>,         // Average predictions over the model's augmented views
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ClassifyParams {
+    pub top_k: Option<usize>, // Query parameters for raw image uploads
+    pub tta: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchImageInput {
+    pub images: Vec<String>,  // Base64-encoded image strings
+    pub top_k: Option<usize>, // Number of best classes to return per image
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClassScore {
+    pub index: i64,
+    pub label: String,
+    pub score: f32,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ImagePrediction {
     pub label: String,
+    pub predictions: Vec<ClassScore>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub augmentations: Option<Vec<String>>, // Views averaged when tta was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejection: Option<String>, // Why the label is "unknown"; predictions keep the raw scores
+}
+
+impl From<Vec<ClassScore>> for ImagePrediction {
+    fn from(predictions: Vec<ClassScore>) -> Self {
+        let label = predictions
+            .first()
+            .map(|prediction| prediction.label.clone())
+            .unwrap_or_default();
+        ImagePrediction {
+            label,
+            predictions,
+            augmentations: None,
+            rejection: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BatchItemResult {
+    Prediction(ImagePrediction),
+    Error(ErrorResponse),
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchPrediction {
+    pub results: Vec<BatchItemResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BoundingBox {
+    pub x_min: f32, // Pixel coordinates in the original image
+    pub y_min: f32,
+    pub x_max: f32,
+    pub y_max: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Detection {
+    pub index: i64,
+    pub label: String,
+    pub score: f32,
+    pub bbox: BoundingBox,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetectionResponse {
+    pub width: u32, // Size of the original image
+    pub height: u32,
+    pub detections: Vec<Detection>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingEncoding {
+    #[default]
+    Float, // JSON array of floats
+    Base64, // Base64 of the little-endian f32 bytes
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbedParams {
+    pub encoding: Option<EmbeddingEncoding>, // Query parameter, defaults to float
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum EmbeddingData {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingResponse {
+    pub model: String,
+    pub dimension: usize,
+    pub encoding: EmbeddingEncoding,
+    pub embedding: EmbeddingData, // L2-normalized
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IndexInsertInput {
+    pub id: String,
+    pub image: Option<String>, // Base64-encoded image, embedded with the index model
+    pub image_url: Option<String>, // Or an image the server downloads itself
+    pub vector: Option<Vec<f32>>, // Or a precomputed embedding
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexInsertResponse {
+    pub id: String,
+    pub dimension: usize,
+    pub replaced: bool, // Whether an entry with this id was overwritten
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IndexQueryInput {
+    pub id: Option<String>, // Neighbours of an entry already in the index
+    pub image: Option<String>,
+    pub image_url: Option<String>,
+    pub vector: Option<Vec<f32>>,
+    pub k: Option<usize>, // Number of neighbours to return
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexMatch {
+    pub id: String,
+    pub score: f32, // Cosine similarity
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexQueryResponse {
+    pub matches: Vec<IndexMatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexInfo {
+    pub entries: usize,
+    pub dimension: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskFormat {
+    #[default]
+    Png, // Base64 8-bit palette PNG whose pixel values are class indices
+    Rle,      // COCO-style uncompressed RLE per class
+    Polygons, // Outer contours per class
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SegmentParams {
+    pub format: Option<MaskFormat>, // Query parameter, defaults to png
+}
+
+#[derive(Debug, Serialize)]
+pub struct SegmentClass {
+    pub index: i64,
+    pub label: String,
+    pub pixels: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClassRle {
+    pub index: i64,
+    pub label: String,
+    pub size: [u32; 2],   // Height, width
+    pub counts: Vec<u32>, // Column-major runs, starting with pixels outside the class
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClassPolygons {
+    pub index: i64,
+    pub label: String,
+    pub polygons: Vec<Vec<[u32; 2]>>, // [x, y] pixel corners, so a pixel spans x..x+1
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SegmentationMask {
+    Png(String),
+    Rle(Vec<ClassRle>),
+    Polygons(Vec<ClassPolygons>),
+}
+
+#[derive(Debug, Serialize)]
+pub struct SegmentationResponse {
+    pub width: u32, // Size of the original image, which the mask matches
+    pub height: u32,
+    pub format: MaskFormat,
+    pub classes: Vec<SegmentClass>, // Classes present in the mask
+    pub mask: SegmentationMask,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelInfo {
+    pub name: String,
+    pub model_path: String,
+    pub class_path: String,
+    pub backend: BackendKind,
+    pub task: ModelTask,
+    pub embedding: bool, // Whether /embed can use this model
+    pub device: String,
+    pub num_classes: usize,
+    pub input_shape: [u32; 3], // Channels, height, width of one preprocessed image
+    pub default: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyUsage {
+    pub name: String,
+    pub requests: u64,  // Every request presenting the key, including rejected ones
+    pub succeeded: u64, // 2xx responses
+    pub failed: u64,    // Other responses from the handlers
+    pub rate_limited: u64, // 429s from the token bucket or the concurrency quota
+    pub in_flight: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageResponse {
+    pub auth_enabled: bool,
+    pub keys: Vec<KeyUsage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelList {
+    pub models: Vec<ModelInfo>,
 }
@@ -1,5 +1,6 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
+use std::path::Path;
 
 pub fn load_classes(path: &str) -> Result<Vec<String>, String> {
     let file = File::open(path).map_err(|err| err.to_string())?;
@@ -11,3 +12,85 @@ pub fn load_classes(path: &str) -> Result<Vec<String>, String> {
 
     Ok(classes)
 }
+
+// Per-class thresholds live next to the class file unless configured elsewhere
+pub fn thresholds_path(class_path: &str, configured: Option<&str>) -> Option<String> {
+    if let Some(path) = configured {
+        return Some(path.to_string());
+    }
+    let path = Path::new(class_path).with_extension("thresholds.txt");
+    path.exists().then(|| path.to_string_lossy().into_owned())
+}
+
+// One "<label> <threshold>" per line; blank lines and lines starting with # are skipped
+pub fn load_class_thresholds(path: &str, classes: &[String]) -> Result<Vec<Option<f32>>, String> {
+    let content = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut thresholds = vec![None; classes.len()];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (label, threshold) = line
+            .rsplit_once(char::is_whitespace)
+            .ok_or_else(|| format!("Invalid threshold line: {}", line))?;
+        let threshold: f32 = threshold
+            .parse()
+            .ok()
+            .filter(|threshold| (0.0..=1.0).contains(threshold))
+            .ok_or_else(|| format!("Invalid threshold line: {}", line))?;
+        let index = classes
+            .iter()
+            .position(|class| class == label.trim())
+            .ok_or_else(|| format!("Unknown class: {}", label.trim()))?;
+        thresholds[index] = Some(threshold);
+    }
+
+    Ok(thresholds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn classes() -> Vec<String> {
+        ["tabby cat", "dog"].map(String::from).to_vec()
+    }
+
+    // Writes the thresholds file under the system temp dir and loads it
+    fn load(name: &str, content: &str) -> Result<Vec<Option<f32>>, String> {
+        let path =
+            env::temp_dir().join(format!("ai_api_{}_{}.thresholds.txt", name, process::id()));
+        fs::write(&path, content).unwrap();
+        let result = load_class_thresholds(path.to_str().unwrap(), &classes());
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn parses_labels_with_spaces_comments_and_blank_lines() {
+        let thresholds = load("valid", "# cats are hard\n\ntabby cat 0.7\n  dog\t1\n").unwrap();
+        assert_eq!(thresholds, vec![Some(0.7), Some(1.0)]);
+        assert_eq!(load("empty", "").unwrap(), vec![None, None]);
+    }
+
+    #[test]
+    fn rejects_unknown_classes() {
+        let err = load("unknown", "tabby cat 0.7\nwolf 0.5\n").unwrap_err();
+        assert_eq!(err, "Unknown class: wolf");
+    }
+
+    #[test]
+    fn rejects_invalid_thresholds() {
+        for line in ["dog 1.5", "dog -0.1", "dog NaN", "dog high", "dog"] {
+            let err = load("invalid", line).unwrap_err();
+            assert!(
+                err.starts_with("Invalid threshold line"),
+                "{}: {}",
+                line,
+                err
+            );
+        }
+    }
+}
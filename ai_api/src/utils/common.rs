@@ -1,7 +1,26 @@
-use std::time::Instant;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::time::{Instant, SystemTime};
 use tracing::info;
 
 pub fn log_elapsed_time(label: &str, start_time: Instant) {
     let elapsed_time = start_time.elapsed();
     info!("⏱️ {} took {:.3?} seconds", label, elapsed_time);
 }
+
+pub fn env_override<T: FromStr>(name: &str, target: &mut T) -> Result<(), String> {
+    if let Ok(value) = env::var(name) {
+        *target = value
+            .parse()
+            .map_err(|_| format!("Invalid value for {}: {}", name, value))?;
+    }
+    Ok(())
+}
+
+pub fn latest_modified(paths: &[&str]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .max()
+}
@@ -0,0 +1,29 @@
+use tch::{Cuda, Device};
+
+pub fn parse_device(value: &str) -> Result<Device, String> {
+    let value = value.trim().to_lowercase();
+    let device = match value.as_str() {
+        "auto" => return Ok(Device::cuda_if_available()),
+        "cpu" => Device::Cpu,
+        "cuda" => Device::Cuda(0),
+        _ => match value.strip_prefix("cuda:") {
+            Some(index) => Device::Cuda(
+                index
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid CUDA device index: {}", index))?,
+            ),
+            None => return Err(format!("Unknown device: {}", value)),
+        },
+    };
+
+    if let Device::Cuda(index) = device {
+        let device_count = Cuda::device_count() as usize;
+        if index >= device_count {
+            return Err(format!(
+                "CUDA device {} requested but only {} available",
+                index, device_count
+            ));
+        }
+    }
+    Ok(device)
+}
@@ -0,0 +1,163 @@
+// Minimal EXIF reader: only the orientation tag is needed, and the image crate
+// does not expose EXIF data for any format
+
+use super::jpeg::segments;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+
+// EXIF orientation (1-8) from JPEG APP1, PNG eXIf, WebP EXIF or a TIFF header
+pub fn exif_orientation(bytes: &[u8]) -> Option<u8> {
+    let tiff = if bytes.starts_with(&[0xFF, 0xD8]) {
+        jpeg_exif(bytes)?
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_exif(bytes)?
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        webp_exif(bytes)?
+    } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        bytes
+    } else {
+        return None;
+    };
+    orientation(tiff)
+}
+
+fn jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    segments(bytes).find_map(|(marker, segment)| match marker {
+        0xE1 => segment.strip_prefix(b"Exif\0\0"),
+        _ => None,
+    })
+}
+
+fn png_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 8;
+    loop {
+        let length = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = bytes.get(pos + 4..pos + 8)?;
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+        match kind {
+            b"eXIf" => return Some(data),
+            b"IEND" => return None,
+            _ => pos += 12 + length, // Length, type, data and CRC
+        }
+    }
+}
+
+fn webp_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 12;
+    loop {
+        let kind = bytes.get(pos..pos + 4)?;
+        let length = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+        if kind == b"EXIF" {
+            return Some(data.strip_prefix(b"Exif\0\0").unwrap_or(data));
+        }
+        pos += 8 + length + (length & 1); // Chunks are padded to an even size
+    }
+}
+
+// Looks the orientation tag up in the first IFD of a TIFF structure
+fn orientation(tiff: &[u8]) -> Option<u8> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = tiff.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let bytes = tiff.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+
+    if read_u16(2)? != 42 {
+        return None;
+    }
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    (0..entries)
+        .map(|entry| ifd + 2 + entry * 12)
+        .find(|&pos| read_u16(pos) == Some(ORIENTATION_TAG))
+        .and_then(|pos| read_u16(pos + 8))
+        .filter(|value| (1..=8).contains(value))
+        .map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A TIFF header with one IFD0 entry holding the orientation as a SHORT
+    fn tiff(big_endian: bool, orientation: u16) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut tiff = if big_endian {
+            b"MM".to_vec()
+        } else {
+            b"II".to_vec()
+        };
+        tiff.extend(u16_bytes(42));
+        tiff.extend(u32_bytes(8));
+        tiff.extend(u16_bytes(1));
+        tiff.extend(u16_bytes(ORIENTATION_TAG));
+        tiff.extend(u16_bytes(3));
+        tiff.extend(u32_bytes(1));
+        tiff.extend(u16_bytes(orientation));
+        tiff.extend([0, 0, 0, 0, 0, 0]);
+        tiff
+    }
+
+    #[test]
+    fn reads_both_byte_orders() {
+        assert_eq!(exif_orientation(&tiff(false, 6)), Some(6));
+        assert_eq!(exif_orientation(&tiff(true, 8)), Some(8));
+    }
+
+    #[test]
+    fn reads_webp_exif_chunk() {
+        let exif = [b"Exif\0\0".as_slice(), &tiff(true, 3)].concat();
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(b"VP8X");
+        webp.extend(10u32.to_le_bytes());
+        webp.extend([0; 10]);
+        webp.extend(b"EXIF");
+        webp.extend((exif.len() as u32).to_le_bytes());
+        webp.extend(&exif);
+        assert_eq!(exif_orientation(&webp), Some(3));
+    }
+
+    #[test]
+    fn ignores_invalid_or_truncated_data() {
+        assert_eq!(exif_orientation(&tiff(false, 0)), None);
+        assert_eq!(exif_orientation(&tiff(false, 9)), None);
+        let truncated = tiff(false, 6);
+        assert_eq!(exif_orientation(&truncated[..12]), None);
+        assert_eq!(exif_orientation(b"GIF89a"), None);
+        // A JPEG whose only segment claims more bytes than the file holds
+        assert_eq!(
+            exif_orientation(&[0xFF, 0xD8, 0xFF, 0xE1, 0x10, 0x00]),
+            None
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use lazy_static::lazy_static;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{redirect, Client, Url};
+use std::time::Duration;
+
+use crate::config::CONFIG;
+use crate::errors::ErrorCode;
+
+const MAX_REDIRECTS: usize = 5;
+
+lazy_static! {
+    static ref HTTP_CLIENT: Client = build_client(
+        Duration::from_secs(CONFIG.url_fetch_timeout_secs),
+        CONFIG.url_allowed_hosts.clone(),
+    );
+}
+
+fn build_client(timeout: Duration, allowed_hosts: Vec<String>) -> Client {
+    Client::builder()
+        .timeout(timeout)
+        .redirect(redirect::Policy::custom(move |attempt| {
+            // Every hop has to stay on the allowlist, not just the first URL
+            if attempt.previous().len() >= MAX_REDIRECTS
+                || !is_allowed(attempt.url(), &allowed_hosts)
+            {
+                attempt.stop()
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+fn is_allowed(url: &Url, allowed_hosts: &[String]) -> bool {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return false;
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_lowercase();
+    allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.to_lowercase();
+        match allowed.strip_prefix("*.") {
+            Some(domain) => host.ends_with(&format!(".{}", domain)),
+            None => host == allowed,
+        }
+    })
+}
+
+pub async fn fetch_image(url: &str) -> Result<Vec<u8>, (ErrorCode, String)> {
+    fetch(
+        &HTTP_CLIENT,
+        url,
+        &CONFIG.url_allowed_hosts,
+        CONFIG.url_fetch_max_bytes,
+    )
+    .await
+}
+
+async fn fetch(
+    client: &Client,
+    url: &str,
+    allowed_hosts: &[String],
+    max_bytes: usize,
+) -> Result<Vec<u8>, (ErrorCode, String)> {
+    let url = Url::parse(url).map_err(|err| (ErrorCode::InvalidInputData, err.to_string()))?;
+    if !is_allowed(&url, allowed_hosts) {
+        return Err((
+            ErrorCode::ImageUrlNotAllowed,
+            format!("Host not allowed: {}", url),
+        ));
+    }
+
+    let mut response = client
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|err| (ErrorCode::ImageFetchFailed, err.to_string()))?;
+    if !response.status().is_success() {
+        return Err((
+            ErrorCode::ImageFetchFailed,
+            format!("{} returned {}", url, response.status()),
+        ));
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+    if !content_type.starts_with("image/") {
+        return Err((
+            ErrorCode::UnsupportedImageFormat,
+            format!("{} has content type {:?}", url, content_type),
+        ));
+    }
+
+    // Stream the body so an oversized or lying Content-Length never gets fully buffered
+    if response.content_length().unwrap_or(0) > max_bytes as u64 {
+        return Err((
+            ErrorCode::ImageTooLarge,
+            format!("{} exceeds {} bytes", url, max_bytes),
+        ));
+    }
+    let mut image_bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| (ErrorCode::ImageFetchFailed, err.to_string()))?
+    {
+        if image_bytes.len() + chunk.len() > max_bytes {
+            return Err((
+                ErrorCode::ImageTooLarge,
+                format!("{} exceeds {} bytes", url, max_bytes),
+            ));
+        }
+        image_bytes.extend_from_slice(&chunk);
+    }
+
+    if image_bytes.is_empty() {
+        return Err((ErrorCode::InvalidInputData, format!("{} is empty", url)));
+    }
+    Ok(image_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const MAX_BYTES: usize = 1024;
+
+    // Minimal HTTP/1.1 server answering canned responses by path and recording every path hit
+    struct Stub {
+        port: u16,
+        paths: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn stub() -> Stub {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let recorded = paths.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+                    recorded.lock().unwrap().push(path.clone());
+                    let response = respond(&path, port).await;
+                    let _ = socket.write_all(&response).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        Stub { port, paths }
+    }
+
+    async fn respond(path: &str, port: u16) -> Vec<u8> {
+        let ok = |content_type: &str, body: &[u8]| {
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                content_type,
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(body);
+            response
+        };
+        match path {
+            "/image.png" => ok("image/png", b"\x89PNG\r\n\x1a\nnot really a png"),
+            "/page.html" => ok("text/html", b"<html></html>"),
+            "/slow.png" => {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                ok("image/png", b"late")
+            }
+            "/redirect-allowed" => format!(
+                "HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1:{}/image.png\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                port
+            )
+            .into_bytes(),
+            "/redirect-elsewhere" => format!(
+                "HTTP/1.1 302 Found\r\nLocation: http://localhost:{}/image.png\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                port
+            )
+            .into_bytes(),
+            "/large-length.png" => format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                MAX_BYTES + 1
+            )
+            .into_bytes(),
+            "/large-chunked.png" => {
+                let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n".to_vec();
+                for _ in 0..4 {
+                    response.extend_from_slice(b"200\r\n"); // 512 bytes per chunk
+                    response.extend_from_slice(&[0u8; 512]);
+                    response.extend_from_slice(b"\r\n");
+                }
+                response.extend_from_slice(b"0\r\n\r\n");
+                response
+            }
+            _ => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_vec(),
+        }
+    }
+
+    async fn fetch_from(stub: &Stub, path: &str) -> Result<Vec<u8>, (ErrorCode, String)> {
+        let allowed_hosts = vec!["127.0.0.1".to_string()];
+        let client = build_client(Duration::from_millis(500), allowed_hosts.clone());
+        let url = format!("http://127.0.0.1:{}{}", stub.port, path);
+        fetch(&client, &url, &allowed_hosts, MAX_BYTES).await
+    }
+
+    fn status(result: Result<Vec<u8>, (ErrorCode, String)>) -> StatusCode {
+        result.unwrap_err().0.status_code()
+    }
+
+    fn allowed(url: &str, hosts: &[&str]) -> bool {
+        let hosts: Vec<String> = hosts.iter().map(|host| host.to_string()).collect();
+        is_allowed(&Url::parse(url).unwrap(), &hosts)
+    }
+
+    #[test]
+    fn wildcard_matches_subdomains_only() {
+        let hosts = ["*.example.com"];
+        assert!(allowed("https://cdn.example.com/a.png", &hosts));
+        assert!(allowed("https://a.b.example.com/a.png", &hosts));
+        assert!(allowed("https://CDN.Example.com/a.png", &hosts));
+        assert!(!allowed("https://example.com/a.png", &hosts));
+        assert!(!allowed("https://badexample.com/a.png", &hosts));
+        assert!(!allowed("https://example.com.evil.net/a.png", &hosts));
+    }
+
+    #[test]
+    fn exact_host_and_scheme_are_checked() {
+        let hosts = ["images.example.com"];
+        assert!(allowed("http://images.example.com/a.png", &hosts));
+        assert!(!allowed("http://cdn.images.example.com/a.png", &hosts));
+        assert!(!allowed("ftp://images.example.com/a.png", &hosts));
+        assert!(!allowed("file:///etc/passwd", &hosts));
+        assert!(!allowed("http://images.example.com/a.png", &[]));
+    }
+
+    #[tokio::test]
+    async fn fetches_allowed_image() {
+        let stub = stub().await;
+        let bytes = fetch_from(&stub, "/image.png").await.unwrap();
+        assert!(bytes.starts_with(b"\x89PNG"));
+    }
+
+    #[tokio::test]
+    async fn follows_redirect_within_allowlist() {
+        let stub = stub().await;
+        assert!(fetch_from(&stub, "/redirect-allowed").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_host_not_on_allowlist() {
+        let stub = stub().await;
+        let allowed_hosts = vec!["images.example.com".to_string()];
+        let client = build_client(Duration::from_millis(500), allowed_hosts.clone());
+        let url = format!("http://127.0.0.1:{}/image.png", stub.port);
+        let result = fetch(&client, &url, &allowed_hosts, MAX_BYTES).await;
+        assert_eq!(status(result), StatusCode::FORBIDDEN);
+        assert!(stub.paths.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuses_redirect_off_allowlist() {
+        let stub = stub().await;
+        let result = fetch_from(&stub, "/redirect-elsewhere").await;
+        assert_eq!(status(result), StatusCode::BAD_GATEWAY);
+        assert_eq!(*stub.paths.lock().unwrap(), ["/redirect-elsewhere"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_non_image_content_type() {
+        let stub = stub().await;
+        let result = fetch_from(&stub, "/page.html").await;
+        assert_eq!(status(result), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_content_length() {
+        let stub = stub().await;
+        let result = fetch_from(&stub, "/large-length.png").await;
+        assert_eq!(status(result), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_chunked_body() {
+        let stub = stub().await;
+        let result = fetch_from(&stub, "/large-chunked.png").await;
+        assert_eq!(status(result), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn times_out_slow_server() {
+        let stub = stub().await;
+        let start = std::time::Instant::now();
+        let result = fetch_from(&stub, "/slow.png").await;
+        assert_eq!(status(result), StatusCode::BAD_GATEWAY);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
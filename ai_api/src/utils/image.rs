@@ -1,23 +1,622 @@
-use image::io::Reader as ImageReader;
-use std::io::Cursor;
+use image::error::LimitErrorKind;
+use image::io::{Limits, Reader as ImageReader};
+use image::{imageops, DynamicImage, ImageError, ImageFormat, Rgb, Rgb32FImage, RgbImage};
+use std::error::Error;
+use std::io::{self, Cursor};
 use tch::{Kind, Tensor};
 
-pub fn preprocess_image(image_bytes: Vec<u8>) -> Result<Tensor, String> {
-    let cursor = Cursor::new(image_bytes);
-    let img = ImageReader::new(cursor)
-        .with_guessed_format()
-        .unwrap()
-        .decode()
-        .unwrap();
-
-    let img = img.resize_exact(224, 224, image::imageops::FilterType::Triangle);
-    let img = img.to_rgb8();
-    let (width, height) = img.dimensions();
-    let mut tensor = Tensor::from_slice(img.as_raw())
-        .reshape([height as i64, width as i64, 3])
-        .permute([2, 0, 1])
+use crate::config::{ChannelOrder, PreprocessConfig, ResizeMode, TtaView, CONFIG};
+use crate::errors::ErrorCode;
+use crate::metrics::{DECODE_SECONDS, PREPROCESS_SECONDS};
+use crate::utils::exif::exif_orientation;
+use crate::utils::jpeg::decode_cmyk;
+
+// Upper bound on the shorter-side resize output, about a 256:1 aspect ratio at resize_size 256
+const MAX_RESIZED_PIXELS: f64 = 4096.0 * 4096.0;
+
+// Maps model-input pixel coordinates back onto the original image
+#[derive(Debug, Clone, Copy)]
+pub struct ImageTransform {
+    pub width: u32,
+    pub height: u32,
+    scale_x: f32,
+    scale_y: f32,
+    offset_x: f32,
+    offset_y: f32,
+}
+
+impl ImageTransform {
+    pub fn to_original(self, x: f32, y: f32) -> (f32, f32) {
+        let x = (x - self.offset_x) / self.scale_x;
+        let y = (y - self.offset_y) / self.scale_y;
+        (
+            x.clamp(0.0, self.width as f32),
+            y.clamp(0.0, self.height as f32),
+        )
+    }
+
+    pub fn to_model(self, x: f32, y: f32) -> (f32, f32) {
+        (
+            x * self.scale_x + self.offset_x,
+            y * self.scale_y + self.offset_y,
+        )
+    }
+}
+
+pub fn preprocess_image(
+    image_bytes: Vec<u8>,
+    config: &PreprocessConfig,
+) -> Result<Tensor, (ErrorCode, String)> {
+    preprocess_image_with_transform(image_bytes, config).map(|(tensor, _)| tensor)
+}
+
+pub fn preprocess_image_with_transform(
+    image_bytes: Vec<u8>,
+    config: &PreprocessConfig,
+) -> Result<(Tensor, ImageTransform), (ErrorCode, String)> {
+    let timer = DECODE_SECONDS.start_timer();
+    let img = decode_image(image_bytes, config)?;
+    timer.observe_duration();
+
+    let _timer = PREPROCESS_SECONDS.start_timer();
+    let (img, transform) = resize_and_crop(img, config)?;
+    Ok((to_tensor(&img, config).unsqueeze(0), transform))
+}
+
+// Builds one [V, 3, H, W] batch holding every configured test-time augmentation view,
+// along with the name of each view
+pub fn preprocess_views(
+    image_bytes: Vec<u8>,
+    config: &PreprocessConfig,
+    views: &[TtaView],
+) -> Result<(Tensor, Vec<String>), (ErrorCode, String)> {
+    let timer = DECODE_SECONDS.start_timer();
+    let img = decode_image(image_bytes, config)?;
+    timer.observe_duration();
+
+    let _timer = PREPROCESS_SECONDS.start_timer();
+    // Crops are taken from the resized image; other resize modes already yield crop_size
+    let base = match config.resize_mode {
+        ResizeMode::ShorterSide => resize_shorter_side(&img, config)?.0,
+        _ => resize_and_crop(img, config)?.0,
+    };
+    let crop = config.crop_size;
+    let (right, bottom) = (base.width() - crop, base.height() - crop);
+    let (center_x, center_y) = (right / 2, bottom / 2);
+
+    let mut crops = Vec::new();
+    for view in views {
+        match view {
+            TtaView::CenterCrop => crops.push(("center_crop", center_x, center_y, false)),
+            TtaView::Hflip => crops.push(("hflip", center_x, center_y, true)),
+            TtaView::FiveCrop => crops.extend([
+                ("top_left_crop", 0, 0, false),
+                ("top_right_crop", right, 0, false),
+                ("bottom_left_crop", 0, bottom, false),
+                ("bottom_right_crop", right, bottom, false),
+                ("center_crop", center_x, center_y, false),
+            ]),
+        }
+    }
+
+    let mut names = Vec::with_capacity(crops.len());
+    let mut tensors = Vec::with_capacity(crops.len());
+    for (name, x, y, flip) in crops {
+        let mut img = base.crop_imm(x, y, crop, crop);
+        if flip {
+            img = img.fliph();
+        }
+        names.push(name.to_string());
+        tensors.push(to_tensor(&img, config));
+    }
+    Ok((Tensor::stack(&tensors, 0), names))
+}
+
+// Converts to a normalized [3, H, W] float tensor in the model's channel order
+fn to_tensor(img: &DynamicImage, config: &PreprocessConfig) -> Tensor {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    let tensor = match img {
+        DynamicImage::ImageRgb8(img) => {
+            Tensor::from_slice(img.as_raw()).to_kind(Kind::Float) / 255.0
+        }
+        _ => Tensor::from_slice(img.to_rgb32f().as_raw()),
+    };
+    let mut tensor = tensor.reshape([height, width, 3]).permute([2, 0, 1]);
+
+    let mean = Tensor::from_slice(&config.mean)
         .to_kind(Kind::Float)
-        / 255.0;
-    tensor = tensor.unsqueeze(0);
-    Ok(tensor)
+        .reshape([3, 1, 1]);
+    let std = Tensor::from_slice(&config.std)
+        .to_kind(Kind::Float)
+        .reshape([3, 1, 1]);
+    tensor = (tensor - mean) / std;
+    if let ChannelOrder::Bgr = config.channel_order {
+        tensor = tensor.flip([0]);
+    }
+    tensor
+}
+
+fn decode_image(
+    image_bytes: Vec<u8>,
+    config: &PreprocessConfig,
+) -> Result<DynamicImage, (ErrorCode, String)> {
+    let orientation = exif_orientation(&image_bytes);
+    let format = check_dimensions(&image_bytes)?;
+    let max_alloc = CONFIG.decoder_alloc_limit(format);
+    let img = match decode_cmyk(&image_bytes, max_alloc) {
+        Some(result) => DynamicImage::ImageRgb8(result?),
+        None => decode_any(image_bytes, format, max_alloc)?,
+    };
+
+    let orientation = orientation.unwrap_or(1);
+    let decoded = img.as_bytes().len() as u64;
+    let rotated = if orientation == 1 { 0 } else { decoded };
+    // The rotated copy is dropped before flattening, the decoded image is not
+    check_memory(
+        decoded + rotated.max(flatten_bytes(&img)),
+        "converting the decoded image",
+    )?;
+    let img = apply_orientation(img, orientation);
+    Ok(flatten_pixels(img, config.background))
+}
+
+// Keeps the buffers a single image holds at once within max_image_memory_bytes
+fn check_memory(bytes: u64, stage: &str) -> Result<(), (ErrorCode, String)> {
+    let limit = CONFIG.max_image_memory_bytes;
+    if bytes > limit {
+        return Err((
+            ErrorCode::ImageMemoryLimitExceeded,
+            format!("{} needs {} bytes, the limit is {}", stage, bytes, limit),
+        ));
+    }
+    Ok(())
+}
+
+// New buffers flatten_pixels allocates for an image
+fn flatten_bytes(img: &DynamicImage) -> u64 {
+    let pixels = img.width() as u64 * img.height() as u64;
+    let per_pixel = match (img, img.color().has_alpha(), is_deep(img)) {
+        (DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgb32F(_), _, _) => 0,
+        (_, false, false) => 3,
+        (_, false, true) => 12,
+        (_, true, false) => 4 + 3,  // RGBA8 copy, then RGB8
+        (_, true, true) => 16 + 12, // RGBA32F copy, then RGB32F
+    };
+    pixels * per_pixel
+}
+
+// Buffers alive while resize_exact scales img to width x height: the source, the
+// vertical pass (RGBA32F at source width) and the output
+fn resize_bytes(img: &DynamicImage, width: u32, height: u32) -> u64 {
+    let source = img.as_bytes().len() as u64;
+    let vertical_pass = img.width() as u64 * height as u64 * 16;
+    let output = width as u64 * height as u64 * img.color().bytes_per_pixel() as u64;
+    source + vertical_pass + output
+}
+
+// Reads only the header, so oversized images are rejected before any pixel is decoded
+fn check_dimensions(image_bytes: &[u8]) -> Result<ImageFormat, (ErrorCode, String)> {
+    let reader = ImageReader::new(Cursor::new(image_bytes))
+        .with_guessed_format()
+        .map_err(|err| (ErrorCode::InvalidInputData, err.to_string()))?;
+    let Some(format) = reader.format() else {
+        return Err((
+            ErrorCode::UnsupportedImageFormat,
+            "Could not detect image format".to_string(),
+        ));
+    };
+
+    let (width, height) = reader.into_dimensions().map_err(decode_error)?;
+    if width == 0 || height == 0 {
+        return Err((
+            ErrorCode::EmptyImage,
+            format!("Image header declares {}x{}", width, height),
+        ));
+    }
+    let pixels = width as u64 * height as u64;
+    if pixels > CONFIG.max_image_pixels {
+        return Err((
+            ErrorCode::ImageDimensionsTooLarge,
+            format!(
+                "{}x{} is {} pixels, the limit is {}",
+                width, height, pixels, CONFIG.max_image_pixels
+            ),
+        ));
+    }
+    Ok(format)
+}
+
+fn decode_any(
+    image_bytes: Vec<u8>,
+    format: ImageFormat,
+    max_alloc: u64,
+) -> Result<DynamicImage, (ErrorCode, String)> {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(max_alloc);
+    let mut reader = ImageReader::with_format(Cursor::new(image_bytes), format);
+    reader.limits(limits);
+
+    reader.decode().map_err(decode_error)
+}
+
+// Rotates and mirrors as EXIF orientation 1-8 describes, so the image is upright
+fn apply_orientation(img: DynamicImage, orientation: u8) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+fn is_deep(img: &DynamicImage) -> bool {
+    !matches!(
+        img,
+        DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_)
+    )
+}
+
+// Composites alpha over the background colour and settles on RGB: 8-bit for 8-bit
+// sources, 32-bit float for 16-bit and float sources so their precision survives resizing
+fn flatten_pixels(img: DynamicImage, background: [u8; 3]) -> DynamicImage {
+    let deep = is_deep(&img);
+    if !img.color().has_alpha() {
+        return if deep {
+            DynamicImage::ImageRgb32F(img.into_rgb32f())
+        } else {
+            DynamicImage::ImageRgb8(img.into_rgb8())
+        };
+    }
+
+    if !deep {
+        let rgba = img.into_rgba8();
+        return DynamicImage::ImageRgb8(RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let [r, g, b, alpha] = rgba.get_pixel(x, y).0;
+            let blend = |channel: u8, background: u8| {
+                let (alpha, channel, background) =
+                    (alpha as u32, channel as u32, background as u32);
+                ((channel * alpha + background * (255 - alpha) + 127) / 255) as u8
+            };
+            Rgb([
+                blend(r, background[0]),
+                blend(g, background[1]),
+                blend(b, background[2]),
+            ])
+        }));
+    }
+
+    let background = background.map(|channel| channel as f32 / 255.0);
+    let rgba = img.into_rgba32f();
+    DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, alpha] = rgba.get_pixel(x, y).0;
+        let blend = |channel: f32, background: f32| channel * alpha + background * (1.0 - alpha);
+        Rgb([
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ])
+    }))
+}
+
+fn decode_error(err: ImageError) -> (ErrorCode, String) {
+    let error_code = match &err {
+        ImageError::Unsupported(_) => ErrorCode::UnsupportedImageFormat,
+        ImageError::Limits(limit) => match limit.kind() {
+            LimitErrorKind::DimensionError => ErrorCode::ImageDimensionsTooLarge,
+            _ => ErrorCode::DecoderLimitExceeded,
+        },
+        _ if is_truncated(&err) => ErrorCode::TruncatedImage,
+        // The PNG decoder refuses a zero width or height in the header itself
+        _ if err.to_string().contains("Invalid image dimensions") => ErrorCode::EmptyImage,
+        _ => ErrorCode::InvalidInputData,
+    };
+    (error_code, err.to_string())
+}
+
+// Decoders report early EOF either as an io::Error or inside a format-specific error
+fn is_truncated(err: &ImageError) -> bool {
+    let mut source: Option<&dyn Error> = Some(err);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<io::Error>() {
+            if io_err.kind() == io::ErrorKind::UnexpectedEof {
+                return true;
+            }
+        }
+        if is_truncated_message(&err.to_string()) {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+pub fn is_truncated_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    // "failed to fill whole buffer" is io::ErrorKind::UnexpectedEof from read_exact
+    message.contains("eof")
+        || message.contains("unexpected end")
+        || message.contains("truncated")
+        || message.contains("failed to fill whole buffer")
+}
+
+fn resize_and_crop(
+    img: DynamicImage,
+    config: &PreprocessConfig,
+) -> Result<(DynamicImage, ImageTransform), (ErrorCode, String)> {
+    let filter = config.interpolation.into();
+    let crop_size = config.crop_size;
+    let (orig_width, orig_height) = (img.width(), img.height());
+    let transform = |scale_x: f64, scale_y: f64, offset_x: f64, offset_y: f64| ImageTransform {
+        width: orig_width,
+        height: orig_height,
+        scale_x: scale_x as f32,
+        scale_y: scale_y as f32,
+        offset_x: offset_x as f32,
+        offset_y: offset_y as f32,
+    };
+
+    let resized = match config.resize_mode {
+        ResizeMode::Exact => {
+            check_memory(
+                resize_bytes(&img, crop_size, crop_size),
+                "resizing the image",
+            )?;
+            (
+                img.resize_exact(crop_size, crop_size, filter),
+                transform(
+                    crop_size as f64 / orig_width as f64,
+                    crop_size as f64 / orig_height as f64,
+                    0.0,
+                    0.0,
+                ),
+            )
+        }
+        ResizeMode::ShorterSide => {
+            let (img, scale) = resize_shorter_side(&img, config)?;
+            let x = (img.width() - crop_size) / 2;
+            let y = (img.height() - crop_size) / 2;
+            (
+                img.crop_imm(x, y, crop_size, crop_size),
+                transform(scale, scale, -(x as f64), -(y as f64)),
+            )
+        }
+        ResizeMode::Letterbox => {
+            // Fit the longer side, then pad the rest evenly with grey
+            let scale = crop_size as f64 / orig_width.max(orig_height) as f64;
+            let width = ((orig_width as f64 * scale).round() as u32).clamp(1, crop_size);
+            let height = ((orig_height as f64 * scale).round() as u32).clamp(1, crop_size);
+            let canvas_bytes = crop_size as u64 * crop_size as u64 * 12;
+            check_memory(
+                resize_bytes(&img, width, height) + canvas_bytes,
+                "resizing the image",
+            )?;
+            let resized = img.resize_exact(width, height, filter);
+
+            let x = (crop_size - width) / 2;
+            let y = (crop_size - height) / 2;
+            let canvas = match resized {
+                DynamicImage::ImageRgb8(resized) => {
+                    let mut canvas = RgbImage::from_pixel(crop_size, crop_size, Rgb([114; 3]));
+                    imageops::overlay(&mut canvas, &resized, x as i64, y as i64);
+                    DynamicImage::ImageRgb8(canvas)
+                }
+                resized => {
+                    let grey = Rgb([114.0 / 255.0; 3]);
+                    let mut canvas = Rgb32FImage::from_pixel(crop_size, crop_size, grey);
+                    imageops::overlay(&mut canvas, &resized.to_rgb32f(), x as i64, y as i64);
+                    DynamicImage::ImageRgb32F(canvas)
+                }
+            };
+            (
+                canvas,
+                transform(
+                    width as f64 / orig_width as f64,
+                    height as f64 / orig_height as f64,
+                    x as f64,
+                    y as f64,
+                ),
+            )
+        }
+    };
+    Ok(resized)
+}
+
+fn resize_shorter_side(
+    img: &DynamicImage,
+    config: &PreprocessConfig,
+) -> Result<(DynamicImage, f64), (ErrorCode, String)> {
+    // Never scale the shorter side below the crop, so the crop is always full size
+    let crop_size = config.crop_size;
+    let resize_size = config.resize_size.max(crop_size) as f64;
+    let (width, height) = (img.width() as f64, img.height() as f64);
+    let scale = resize_size / width.min(height);
+    let (resized_width, resized_height) = (width * scale, height * scale);
+    // Thin strips scale their long side by the same factor, which can overflow u32
+    if resized_width * resized_height > MAX_RESIZED_PIXELS {
+        return Err((
+            ErrorCode::ImageDimensionsTooLarge,
+            format!(
+                "{}x{} would be resized to {:.0}x{:.0}, the limit is {} pixels",
+                img.width(),
+                img.height(),
+                resized_width,
+                resized_height,
+                MAX_RESIZED_PIXELS
+            ),
+        ));
+    }
+    let width = (resized_width.round() as u32).max(crop_size);
+    let height = (resized_height.round() as u32).max(crop_size);
+    check_memory(resize_bytes(img, width, height), "resizing the image")?;
+
+    Ok((
+        img.resize_exact(width, height, config.interpolation.into()),
+        scale,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    // Colours at the top-left, top-right, bottom-left and bottom-right corners
+    type Corners = [[u8; 3]; 4];
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read(&path).unwrap_or_else(|err| panic!("{}: {}", path, err))
+    }
+
+    fn decode(bytes: &[u8], background: [u8; 3]) -> Result<DynamicImage, (ErrorCode, String)> {
+        let config = PreprocessConfig {
+            background,
+            ..PreprocessConfig::default()
+        };
+        decode_image(bytes.to_vec(), &config)
+    }
+
+    fn assert_close(actual: [u8; 3], expected: [u8; 3], tolerance: u8) {
+        let close = actual
+            .iter()
+            .zip(expected)
+            .all(|(a, e)| a.abs_diff(e) <= tolerance);
+        assert!(close, "{:?} is not close to {:?}", actual, expected);
+    }
+
+    // The fixtures store a 64x32 image with red, green, blue and white quadrants (reading order)
+    // and an EXIF orientation tag; corners are listed after applying it, per the EXIF spec
+    fn check_orientation(bytes: &[u8], orientation: u8, corners: Corners) {
+        let img = decode(bytes, WHITE).unwrap().to_rgb8();
+        let (width, height) = if orientation >= 5 { (32, 64) } else { (64, 32) };
+        assert_eq!(
+            img.dimensions(),
+            (width, height),
+            "orientation {}",
+            orientation
+        );
+        let points = [
+            (4, 4),
+            (width - 5, 4),
+            (4, height - 5),
+            (width - 5, height - 5),
+        ];
+        for ((x, y), expected) in points.into_iter().zip(corners) {
+            assert_close(img.get_pixel(x, y).0, expected, 8);
+        }
+    }
+
+    #[test]
+    fn applies_every_jpeg_exif_orientation() {
+        let cases: [(u8, Corners); 8] = [
+            (1, [RED, GREEN, BLUE, WHITE]),
+            (2, [GREEN, RED, WHITE, BLUE]),
+            (3, [WHITE, BLUE, GREEN, RED]),
+            (4, [BLUE, WHITE, RED, GREEN]),
+            (5, [RED, BLUE, GREEN, WHITE]),
+            (6, [BLUE, RED, WHITE, GREEN]),
+            (7, [WHITE, GREEN, BLUE, RED]),
+            (8, [GREEN, WHITE, RED, BLUE]),
+        ];
+        for (orientation, corners) in cases {
+            let bytes = fixture(&format!("orientation_{}.jpg", orientation));
+            check_orientation(&bytes, orientation, corners);
+        }
+    }
+
+    #[test]
+    fn applies_png_exif_orientation() {
+        let bytes = fixture("orientation_6.png");
+        check_orientation(&bytes, 6, [BLUE, RED, WHITE, GREEN]);
+    }
+
+    #[test]
+    fn composites_alpha_over_background() {
+        // Opaque red, transparent green, half-transparent white, opaque black
+        let bytes = fixture("rgba.png");
+        let DynamicImage::ImageRgb8(img) = decode(&bytes, BLUE).unwrap() else {
+            panic!("8-bit RGBA should flatten to Rgb8");
+        };
+        assert_close(img.get_pixel(0, 0).0, RED, 0);
+        assert_close(img.get_pixel(1, 0).0, BLUE, 0);
+        assert_close(img.get_pixel(0, 1).0, [128, 128, 255], 1);
+        assert_close(img.get_pixel(1, 1).0, [0, 0, 0], 0);
+    }
+
+    #[test]
+    fn decodes_plain_and_adobe_cmyk_jpegs() {
+        // Pure cyan ink, then 50% black; the Adobe file stores the same ink inverted
+        let plain = fixture("cmyk.jpg");
+        let adobe = fixture("cmyk_adobe.jpg");
+        for bytes in [plain, adobe] {
+            let img = decode(&bytes, WHITE).unwrap().to_rgb8();
+            assert_eq!(img.dimensions(), (16, 8));
+            assert_close(img.get_pixel(3, 3).0, [0, 255, 255], 1);
+            assert_close(img.get_pixel(12, 3).0, [127, 127, 127], 1);
+        }
+    }
+
+    #[test]
+    fn keeps_16_bit_precision() {
+        let bytes = fixture("luma16.png");
+        let DynamicImage::ImageRgb32F(img) = decode(&bytes, WHITE).unwrap() else {
+            panic!("16-bit input should decode to Rgb32F");
+        };
+        let values: Vec<f32> = img.pixels().map(|pixel| pixel.0[0]).collect();
+        assert_eq!(values, [0.0, 32896.0 / 65535.0, 32897.0 / 65535.0, 1.0]);
+        assert!(img
+            .pixels()
+            .all(|pixel| pixel.0[0] == pixel.0[1] && pixel.0[1] == pixel.0[2]));
+
+        let bytes = fixture("rgb16.png");
+        let DynamicImage::ImageRgb32F(img) = decode(&bytes, WHITE).unwrap() else {
+            panic!("16-bit input should decode to Rgb32F");
+        };
+        assert_eq!(img.get_pixel(0, 0).0, [1.0, 0.0, 1000.0 / 65535.0]);
+        assert_eq!(
+            img.get_pixel(1, 0).0,
+            [1.0 / 65535.0, 2.0 / 65535.0, 3.0 / 65535.0]
+        );
+    }
+
+    #[test]
+    fn budgets_intermediate_buffers() {
+        let rgba = DynamicImage::ImageRgba8(image::RgbaImage::new(100, 50));
+        assert_eq!(flatten_bytes(&rgba), 100 * 50 * 7);
+        let rgb = DynamicImage::ImageRgb8(RgbImage::new(100, 50));
+        assert_eq!(flatten_bytes(&rgb), 0);
+        assert_eq!(
+            resize_bytes(&rgb, 200, 100),
+            100 * 50 * 3 + 100 * 100 * 16 + 200 * 100 * 3
+        );
+
+        let limit = CONFIG.max_image_memory_bytes;
+        assert!(check_memory(limit, "resizing").is_ok());
+        let (error_code, _) = check_memory(limit + 1, "resizing").unwrap_err();
+        assert!(matches!(error_code, ErrorCode::ImageMemoryLimitExceeded));
+    }
+
+    #[test]
+    fn reports_precise_error_codes() {
+        for (name, expected) in [
+            ("zero_width.png", ErrorCode::EmptyImage),
+            ("zero_size.gif", ErrorCode::EmptyImage),
+            ("unknown_format.bin", ErrorCode::UnsupportedImageFormat),
+            ("truncated.jpg", ErrorCode::TruncatedImage),
+        ] {
+            let (error_code, message) = decode(&fixture(name), WHITE).unwrap_err();
+            assert_eq!(error_code, expected, "{}: {}", name, message);
+        }
+    }
 }
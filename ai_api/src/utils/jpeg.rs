@@ -0,0 +1,122 @@
+use image::{Rgb, RgbImage};
+use jpeg_decoder::{ColorTransform, Decoder, PixelFormat};
+use std::io::Cursor;
+
+use crate::errors::ErrorCode;
+use crate::utils::image::is_truncated_message;
+
+// Adobe APP14 transform flag for plain (not YCCK) CMYK data
+const ADOBE_TRANSFORM_CMYK: u8 = 0;
+
+// Marker and payload of every JPEG segment before the image data
+pub fn segments(bytes: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut pos = if bytes.starts_with(&[0xFF, 0xD8]) {
+        2
+    } else {
+        bytes.len()
+    };
+    std::iter::from_fn(move || loop {
+        if *bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        if marker == 0xFF {
+            pos += 1; // Fill byte
+            continue;
+        }
+        if marker == 0xD9 || marker == 0xDA {
+            return None;
+        }
+        let length = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + length)?;
+        pos += 2 + length;
+        return Some((marker, segment));
+    })
+}
+
+// Decodes plain CMYK JPEGs, or returns None for anything else. The image crate assumes
+// every CMYK JPEG stores inverted values, as Adobe software does, which renders the rest
+// as a colour negative.
+pub fn decode_cmyk(bytes: &[u8], max_alloc: u64) -> Option<Result<RgbImage, (ErrorCode, String)>> {
+    let mut components = None;
+    let mut adobe_transform = None;
+    for (marker, segment) in segments(bytes) {
+        match marker {
+            // Start-of-frame markers; C4, C8 and CC share the range but mean something else
+            0xC0..=0xCF if ![0xC4, 0xC8, 0xCC].contains(&marker) => {
+                components = segment.get(5).copied();
+            }
+            0xEE if segment.starts_with(b"Adobe") => adobe_transform = segment.get(11).copied(),
+            _ => {}
+        }
+    }
+    if components != Some(4) || adobe_transform.is_some_and(|flag| flag != ADOBE_TRANSFORM_CMYK) {
+        return None;
+    }
+
+    Some(decode_cmyk_data(
+        bytes,
+        adobe_transform.is_some(),
+        max_alloc,
+    ))
+}
+
+fn decode_cmyk_data(
+    bytes: &[u8],
+    inverted: bool,
+    max_alloc: u64,
+) -> Result<RgbImage, (ErrorCode, String)> {
+    let invalid = |err: String| {
+        let error_code = if is_truncated_message(&err) {
+            ErrorCode::TruncatedImage
+        } else {
+            ErrorCode::InvalidInputData
+        };
+        (error_code, err)
+    };
+
+    let mut decoder = Decoder::new(Cursor::new(bytes));
+    // ColorTransform::None emits each row plane by plane rather than pixel by pixel, so use
+    // the CMYK transform, which interleaves but also inverts every sample
+    decoder.set_color_transform(ColorTransform::CMYK);
+    decoder
+        .read_info()
+        .map_err(|err| invalid(err.to_string()))?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| invalid("Missing JPEG header".to_string()))?;
+    // Four bytes per pixel of CMYK data, plus the RGB copy
+    let needed = info.width as u64 * info.height as u64 * 7;
+    if needed > max_alloc {
+        return Err((
+            ErrorCode::DecoderLimitExceeded,
+            format!(
+                "Decoding needs {} bytes, the limit is {}",
+                needed, max_alloc
+            ),
+        ));
+    }
+    decoder.set_max_decoding_buffer_size(max_alloc.try_into().unwrap_or(usize::MAX));
+
+    let data = decoder.decode().map_err(|err| invalid(err.to_string()))?;
+    if info.pixel_format != PixelFormat::CMYK32 {
+        return Err(invalid(format!(
+            "Unexpected pixel format {:?}",
+            info.pixel_format
+        )));
+    }
+    let mut pixels = data.chunks_exact(4).map(|pixel| {
+        let ink = |value: u8| u32::from(if inverted { value } else { 255 - value });
+        let white = 255 - ink(pixel[3]);
+        let channel = |value: u8| ((255 - ink(value)) * white / 255) as u8;
+        Rgb([channel(pixel[0]), channel(pixel[1]), channel(pixel[2])])
+    });
+    let (width, height) = (info.width as u32, info.height as u32);
+    let mut img = RgbImage::new(width, height);
+    for pixel in img.pixels_mut() {
+        *pixel = pixels
+            .next()
+            .ok_or_else(|| invalid("JPEG data is truncated".to_string()))?;
+    }
+    Ok(img)
+}
@@ -1,3 +1,7 @@
 pub mod classes;
 pub mod common;
+pub mod device;
+pub mod exif;
+pub mod fetch;
 pub mod image;
+pub mod jpeg;
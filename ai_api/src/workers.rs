@@ -0,0 +1,96 @@
+use lazy_static::lazy_static;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::oneshot;
+use tracing::{error, Span};
+
+use crate::config::CONFIG;
+use crate::errors::ErrorCode;
+use crate::request_id;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+lazy_static! {
+    pub static ref WORKERS: WorkerPool =
+        WorkerPool::new(CONFIG.worker_threads, CONFIG.worker_queue_size);
+}
+
+// Dedicated threads for image decoding and libtorch calls, so blocking work never
+// runs on the async runtime
+pub struct WorkerPool {
+    sender: mpsc::Sender<Job>,
+}
+
+impl WorkerPool {
+    fn new(threads: usize, queue_size: usize) -> WorkerPool {
+        let (sender, receiver) = mpsc::channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..threads {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("inference-{}", index))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().blocking_recv();
+                    let Some(job) = job else {
+                        break;
+                    };
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("Inference worker job panicked");
+                    }
+                })
+                .expect("Failed to spawn inference worker");
+        }
+
+        WorkerPool { sender }
+    }
+
+    // Rejects the job with ServerBusy when the queue is full; used for new requests
+    pub async fn try_run<F, T>(&self, f: F) -> Result<T, (ErrorCode, String)>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, result) = wrap(f);
+        self.sender.try_send(job).map_err(|err| match err {
+            TrySendError::Full(_) => (ErrorCode::ServerBusy, "Worker queue is full".to_string()),
+            TrySendError::Closed(_) => (ErrorCode::InferenceFailed, err.to_string()),
+        })?;
+        result
+            .await
+            .map_err(|err| (ErrorCode::InferenceFailed, err.to_string()))
+    }
+
+    // Waits for a queue slot; used for work that was already admitted, like batched forwards
+    pub async fn run<F, T>(&self, f: F) -> Result<T, (ErrorCode, String)>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, result) = wrap(f);
+        self.sender
+            .send(job)
+            .await
+            .map_err(|err| (ErrorCode::InferenceFailed, err.to_string()))?;
+        result
+            .await
+            .map_err(|err| (ErrorCode::InferenceFailed, err.to_string()))
+    }
+}
+
+fn wrap<F, T>(f: F) -> (Job, oneshot::Receiver<T>)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    // Jobs keep the request's span and id, so worker logs and errors can be traced back
+    let (sender, receiver) = oneshot::channel();
+    let span = Span::current();
+    let request_id = request_id::current();
+    let job = Box::new(move || {
+        let _entered = span.enter();
+        let _ = sender.send(request_id::with_request_id(request_id, f));
+    });
+    (job, receiver)
+}